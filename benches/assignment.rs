@@ -0,0 +1,75 @@
+//! Times matching robots with work every step for growing amounts of both. Run with `cargo bench`.
+
+extern crate cgmath;
+extern crate shift_inc;
+
+use std::path::Path;
+use std::time::Instant;
+use cgmath::Vector2;
+use shift_inc::robots::{Robots, Robot, WorkQueue, WorkEntry, WorkKind};
+use shift_inc::tile_defs::TileDefs;
+use shift_inc::tiles::Tiles;
+use shift_inc::tmx::MapFile;
+
+const RUNS: u32 = 5;
+// Each step has new work show up
+const STEPS: usize = 60;
+
+fn load_tiles() -> Tiles {
+    let file = MapFile::load(Path::new("assets/map.tmx")).unwrap();
+    let defs = TileDefs::load(&file.tileset_tiles).unwrap();
+    Tiles::load(&file.map, defs, &mut WorkQueue::new()).unwrap()
+}
+
+fn open_tiles(tiles: &Tiles) -> Vec<Vector2<u32>> {
+    let mut open = Vec::new();
+    tiles.for_each(|x, y, _| {
+        if tiles.is_passable(x, y) {
+            open.push(Vector2::new(x, y));
+        }
+    });
+    open
+}
+
+// Picks open tiles with a different stride for robots and work, so they don't overlap
+fn work_tile(open: &[Vector2<u32>], i: usize) -> Vector2<u32> {
+    open[(i * 13 + 5) % open.len()]
+}
+
+// Robots and half as much work spread over the open tiles, like a busy colony
+fn populate(open: &[Vector2<u32>], amount: usize) -> (Robots, WorkQueue) {
+    let mut robots = Robots::new();
+    let mut work = WorkQueue::new();
+    for i in 0..amount {
+        let robot_tile = open[(i * 7) % open.len()];
+        robots.add(Robot::new(robot_tile.cast::<f32>() + Vector2::new(0.5, 0.5)));
+    }
+    for i in 0..amount / 2 {
+        work.publish(WorkEntry::new(WorkKind::Build, work_tile(open, i)));
+    }
+
+    (robots, work)
+}
+
+fn main() {
+    let tiles = load_tiles();
+    let open = open_tiles(&tiles);
+
+    for &amount in &[10, 100, 300, 500] {
+        let mut total = 0.0;
+        for _ in 0..RUNS {
+            let (mut robots, mut work) = populate(&open, amount);
+
+            // Every step a bit more work comes in for the robots still waiting around
+            let start = Instant::now();
+            for step in 0..STEPS {
+                work.publish(WorkEntry::new(WorkKind::Build, work_tile(&open, amount + step)));
+                robots.assign_work(&mut work, &tiles);
+            }
+            let elapsed = start.elapsed();
+            total += elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 / 1000000.0;
+        }
+
+        println!("{} robots: {:.3} ms per step", amount, total / (RUNS as usize * STEPS) as f64);
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::iter::Enumerate;
+use std::slice;
+use save::{SaveWriter, SaveReader, SaveError};
+
+// Slots get reused, the generation makes handles to removed values stop working
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Handle {
+    index: u32,
+    generation: u32,
+}
+
+impl Handle {
+    pub fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_u32(self.index)?;
+        w.write_u32(self.generation)
+    }
+
+    pub fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let index = r.read_u32()?;
+        let generation = r.read_u32()?;
+        Ok(Handle { index: index, generation: generation })
+    }
+}
+
+impl fmt::Display for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+// Values with stable handles, removed values' slots get reused
+pub struct Arena<T> {
+    slots: Vec<Slot<T>>,
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Self {
+        Arena {
+            slots: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|s| s.value.is_some()).count()
+    }
+
+    pub fn insert(&mut self, value: T) -> Handle {
+        // Find an empty slot
+        for i in 0..self.slots.len() {
+            let slot = &mut self.slots[i];
+            if slot.value.is_some() { continue; }
+
+            // Found a slot, the generation was already bumped when it was emptied
+            slot.value = Some(value);
+            return Handle { index: i as u32, generation: slot.generation };
+        }
+
+        // Couldn't find one, add a new one
+        self.slots.push(Slot {
+            generation: 0,
+            value: Some(value),
+        });
+        Handle { index: self.slots.len() as u32 - 1, generation: 0 }
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> Option<T> {
+        if self.get(handle).is_none() {
+            return None;
+        }
+
+        // Bump the generation so existing handles to this slot stop working
+        let slot = &mut self.slots[handle.index as usize];
+        slot.generation += 1;
+        slot.value.take()
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<&T> {
+        self.slots.get(handle.index as usize)
+            .and_then(|s| if s.generation == handle.generation { s.value.as_ref() } else { None })
+    }
+
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut T> {
+        self.slots.get_mut(handle.index as usize)
+            .and_then(|s| if s.generation == handle.generation { s.value.as_mut() } else { None })
+    }
+
+    pub fn iter<'a>(&'a self) -> Iter<'a, T> {
+        Iter {
+            slots: self.slots.iter().enumerate(),
+        }
+    }
+
+    pub fn iter_mut<'a>(&'a mut self) -> IterMut<'a, T> {
+        IterMut {
+            slots: self.slots.iter_mut().enumerate(),
+        }
+    }
+
+    // Empty slots are written too, so handles stay valid after loading
+    pub fn write_save<W, F>(&self, w: &mut SaveWriter<W>, mut f: F) -> io::Result<()>
+        where W: Write, F: FnMut(&T, &mut SaveWriter<W>) -> io::Result<()>
+    {
+        w.write_len(self.slots.len())?;
+        for slot in &self.slots {
+            w.write_u32(slot.generation)?;
+            w.write_option(slot.value.as_ref(), |w, v| f(v, w))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_save<R, F>(r: &mut SaveReader<R>, mut f: F) -> Result<Self, SaveError>
+        where R: Read, F: FnMut(&mut SaveReader<R>) -> Result<T, SaveError>
+    {
+        let amount = r.read_len()?;
+        let mut slots = Vec::new();
+        for _ in 0..amount {
+            let generation = r.read_u32()?;
+            let value = r.read_option(|r| f(r))?;
+            slots.push(Slot {
+                generation: generation,
+                value: value,
+            });
+        }
+
+        Ok(Arena {
+            slots: slots,
+        })
+    }
+
+    // Removes the values the function returns true for and returns their handles
+    pub fn remove_if<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> Vec<Handle> {
+        let mut removed = Vec::new();
+
+        for i in 0..self.slots.len() {
+            let slot = &mut self.slots[i];
+
+            let kill = slot.value.as_ref().map(&mut f).unwrap_or(false);
+            if kill {
+                removed.push(Handle { index: i as u32, generation: slot.generation });
+                slot.generation += 1;
+                slot.value = None;
+            }
+        }
+
+        removed
+    }
+}
+
+pub struct Iter<'a, T: 'a> {
+    slots: Enumerate<slice::Iter<'a, Slot<T>>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (Handle, &'a T);
+
+    fn next(&mut self) -> Option<(Handle, &'a T)> {
+        for (i, slot) in self.slots.by_ref() {
+            if let Some(ref value) = slot.value {
+                return Some((Handle { index: i as u32, generation: slot.generation }, value));
+            }
+        }
+
+        None
+    }
+}
+
+pub struct IterMut<'a, T: 'a> {
+    slots: Enumerate<slice::IterMut<'a, Slot<T>>>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = (Handle, &'a mut T);
+
+    fn next(&mut self) -> Option<(Handle, &'a mut T)> {
+        for (i, slot) in self.slots.by_ref() {
+            let generation = slot.generation;
+            if let Some(ref mut value) = slot.value {
+                return Some((Handle { index: i as u32, generation: generation }, value));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Arena;
+
+    #[test]
+    fn stale_handle_misses_reused_slot() {
+        let mut arena = Arena::new();
+        let old = arena.insert("old");
+        assert_eq!(arena.remove(old), Some("old"));
+
+        // The new value gets the same slot, but the old handle shouldn't see it
+        let new = arena.insert("new");
+        assert_eq!(new.index, old.index);
+        assert_eq!(arena.get(old), None);
+        assert_eq!(arena.get_mut(old), None);
+        assert_eq!(arena.remove(old), None);
+        assert_eq!(arena.get(new), Some(&"new"));
+    }
+
+    #[test]
+    fn remove_if_invalidates_handles() {
+        let mut arena = Arena::new();
+        let a = arena.insert(1);
+        let b = arena.insert(2);
+
+        assert_eq!(arena.remove_if(|v| *v == 1), vec![a]);
+        arena.insert(3);
+        assert_eq!(arena.get(a), None);
+        assert_eq!(arena.get(b), Some(&2));
+        assert_eq!(arena.len(), 2);
+    }
+}
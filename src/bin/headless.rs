@@ -0,0 +1,148 @@
+// The same clippy lints as the library are left off
+#![allow(clippy::redundant_field_names, clippy::redundant_static_lifetimes)]
+
+extern crate shift_inc;
+
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::process;
+use shift_inc::model::GameModel;
+use shift_inc::replay::Recording;
+use shift_inc::rng;
+
+const USAGE: &'static str = "Usage: headless <map.tmx> <ticks> --out <file> [--delta <seconds>] [--seed <n>]
+       headless --replay <recording> --out <file>";
+
+enum Mode {
+    Simulate {
+        map: String,
+        ticks: u32,
+        delta: f32,
+        seed: u64,
+    },
+    Replay(String),
+}
+
+struct Options {
+    mode: Mode,
+    out: String,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut positional = Vec::new();
+    let mut delta = 1.0 / 60.0;
+    let mut seed = None;
+    let mut replay = None;
+    let mut out = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--delta" => {
+                let value = args.next().ok_or("--delta needs a value")?;
+                delta = value.parse().ok()
+                    .and_then(|v: f32| if v.is_finite() && v > 0.0 { Some(v) } else { None })
+                    .ok_or_else(|| format!("Invalid delta \"{}\"", value))?;
+            },
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a value")?;
+                seed = Some(value.parse().map_err(|_| format!("Invalid seed \"{}\"", value))?);
+            },
+            "--replay" => {
+                replay = Some(args.next().ok_or("--replay needs a value")?);
+            },
+            "--out" => {
+                out = Some(args.next().ok_or("--out needs a value")?);
+            },
+            _ => positional.push(arg),
+        }
+    }
+
+    // The state goes to its own file, so it doesn't get mixed up with the log
+    let out = out.ok_or_else(|| USAGE.to_string())?;
+
+    // A replay already knows its map, seed and length
+    if let Some(replay) = replay {
+        if !positional.is_empty() {
+            return Err(USAGE.to_string());
+        }
+
+        return Ok(Options {
+            mode: Mode::Replay(replay),
+            out: out,
+        });
+    }
+
+    if positional.len() != 2 {
+        return Err(USAGE.to_string());
+    }
+    let ticks = positional[1].parse().map_err(|_| format!("Invalid tick count \"{}\"", positional[1]))?;
+
+    Ok(Options {
+        mode: Mode::Simulate {
+            map: positional[0].clone(),
+            ticks: ticks,
+            delta: delta,
+            seed: seed.unwrap_or_else(rng::random_seed),
+        },
+        out: out,
+    })
+}
+
+fn load_model(map: &str, seed: u64) -> GameModel {
+    println!("Using seed {}", seed);
+
+    let path = Path::new(map);
+    match GameModel::load(path, seed) {
+        Ok(model) => model,
+        Err(e) => {
+            println!("Error loading map {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+fn run_replay(path: &str) -> GameModel {
+    let recording = match File::open(path).map_err(|e| e.into()).and_then(|f| Recording::read(BufReader::new(f))) {
+        Ok(recording) => recording,
+        Err(e) => {
+            println!("Error loading recording {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut model = load_model(recording.map(), recording.seed());
+    model.replay(&recording);
+    println!("Replayed {} steps", recording.end_step());
+
+    model
+}
+
+fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    let model = match options.mode {
+        Mode::Simulate { ref map, ticks, delta, seed } => {
+            let mut model = load_model(map, seed);
+            model.simulate(ticks, delta);
+            println!("Simulated {} ticks of {} seconds", ticks, delta);
+
+            model
+        },
+        Mode::Replay(ref path) => run_replay(path),
+    };
+
+    // Write out the final state
+    if let Err(e) = File::create(&options.out).and_then(|mut file| model.map().dump(&mut file)) {
+        println!("Error writing state: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,208 @@
+use std::cmp;
+use std::collections::HashMap;
+use cgmath::Vector2;
+use arena::Handle;
+
+// Finds values near a point or in an area without going over all of them
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<Handle>>,
+    locations: HashMap<Handle, (i32, i32)>,
+    // The area any cell has ever been used in, searches don't need to go further than this
+    min_cell: (i32, i32),
+    max_cell: (i32, i32),
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialGrid {
+            cell_size: cell_size,
+            cells: HashMap::new(),
+            locations: HashMap::new(),
+            min_cell: (0, 0),
+            max_cell: (0, 0),
+        }
+    }
+
+    fn cell_of(&self, position: Vector2<f32>) -> (i32, i32) {
+        ((position.x / self.cell_size).floor() as i32, (position.y / self.cell_size).floor() as i32)
+    }
+
+    pub fn insert(&mut self, id: Handle, position: Vector2<f32>) {
+        let cell = self.cell_of(position);
+        if self.locations.is_empty() {
+            self.min_cell = cell;
+            self.max_cell = cell;
+        } else {
+            self.min_cell = (cmp::min(self.min_cell.0, cell.0), cmp::min(self.min_cell.1, cell.1));
+            self.max_cell = (cmp::max(self.max_cell.0, cell.0), cmp::max(self.max_cell.1, cell.1));
+        }
+
+        self.cells.entry(cell).or_insert_with(Vec::new).push(id);
+        self.locations.insert(id, cell);
+    }
+
+    pub fn remove(&mut self, id: Handle) {
+        if let Some(cell) = self.locations.remove(&id) {
+            let empty = {
+                let ids = self.cells.get_mut(&cell).unwrap();
+                ids.retain(|v| *v != id);
+                ids.is_empty()
+            };
+            if empty {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    pub fn update(&mut self, id: Handle, position: Vector2<f32>) {
+        if self.locations.get(&id) != Some(&self.cell_of(position)) {
+            self.remove(id);
+            self.insert(id, position);
+        }
+    }
+
+    // Values in cells overlapping the area, these can be a bit outside of it
+    pub fn in_area(&self, min: Vector2<f32>, max: Vector2<f32>) -> Vec<Handle> {
+        let (min_x, min_y) = self.cell_of(min);
+        let (max_x, max_y) = self.cell_of(max);
+
+        let mut found = Vec::new();
+        for x in min_x..max_x + 1 {
+            for y in min_y..max_y + 1 {
+                if let Some(ids) = self.cells.get(&(x, y)) {
+                    found.extend(ids.iter().cloned());
+                }
+            }
+        }
+        found
+    }
+
+    // The function gives squared distances or None to skip, ties go to the lowest handle
+    pub fn nearest<F: FnMut(Handle) -> Option<f32>>(&self, position: Vector2<f32>, mut distance2: F)
+        -> Option<Handle>
+    {
+        if self.locations.is_empty() {
+            return None;
+        }
+
+        // Search outwards in rings of cells, until no ring can have anything closer
+        let center = self.cell_of(position);
+        let max_radius = *[
+            center.0 - self.min_cell.0, self.max_cell.0 - center.0,
+            center.1 - self.min_cell.1, self.max_cell.1 - center.1,
+        ].iter().max().unwrap();
+
+        let mut best: Option<(f32, Handle)> = None;
+        for radius in 0..cmp::max(max_radius, 0) + 1 {
+            // Anything in this ring is at least this far away
+            if let Some((distance, _)) = best {
+                let reach = (radius - 1) as f32 * self.cell_size;
+                if radius > 0 && distance < reach * reach {
+                    break;
+                }
+            }
+
+            for dx in -radius..radius + 1 {
+                for dy in -radius..radius + 1 {
+                    // Only the edge of the ring, the inside has already been checked
+                    if dx.abs() != radius && dy.abs() != radius {
+                        continue;
+                    }
+
+                    let ids = if let Some(ids) = self.cells.get(&(center.0 + dx, center.1 + dy)) {
+                        ids
+                    } else {
+                        continue;
+                    };
+
+                    for id in ids {
+                        if let Some(distance) = distance2(*id) {
+                            let better = match best {
+                                Some((best_distance, best_id)) =>
+                                    distance < best_distance || (distance == best_distance && *id < best_id),
+                                None => true,
+                            };
+                            if better {
+                                best = Some((distance, *id));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        best.map(|(_, id)| id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector2;
+    use arena::{Arena, Handle};
+    use super::SpatialGrid;
+
+    // Squared distance from where the value's listed, like items give it
+    fn distance2(positions: &[(Handle, Vector2<f32>)], from: Vector2<f32>, id: Handle) -> Option<f32> {
+        positions.iter().find(|p| p.0 == id).map(|p| {
+            let d = p.1 - from;
+            d.x * d.x + d.y * d.y
+        })
+    }
+
+    #[test]
+    fn nearest_looks_past_its_own_cell() {
+        let mut handles = Arena::new();
+        let (far, near) = (handles.insert(()), handles.insert(()));
+        let mut positions = vec![(far, Vector2::new(0.1, 0.1)), (near, Vector2::new(4.2, 3.9))];
+
+        let mut grid = SpatialGrid::new(4.0);
+        for &(id, position) in &positions {
+            grid.insert(id, position);
+        }
+
+        // The far one shares our cell, but the one over the edge is closer
+        let from = Vector2::new(3.9, 3.9);
+        assert_eq!(grid.nearest(from, |id| distance2(&positions, from, id)), Some(near));
+
+        // Values the distance function skips are never picked
+        let skip_near = |id| if id == near { None } else { distance2(&positions, from, id) };
+        assert_eq!(grid.nearest(from, skip_near), Some(far));
+        assert_eq!(grid.nearest(from, |_| None), None);
+
+        // Once moved away, the other one is closest
+        positions[1].1 = Vector2::new(20.0, 20.0);
+        grid.update(near, positions[1].1);
+        assert_eq!(grid.nearest(from, |id| distance2(&positions, from, id)), Some(far));
+    }
+
+    #[test]
+    fn nearest_breaks_ties_by_handle() {
+        let mut handles = Arena::new();
+        let (first, second) = (handles.insert(()), handles.insert(()));
+
+        // Added in reverse, the lowest handle should still win
+        let mut grid = SpatialGrid::new(4.0);
+        grid.insert(second, Vector2::new(1.0, 0.0));
+        grid.insert(first, Vector2::new(-1.0, 0.0));
+        assert_eq!(grid.nearest(Vector2::new(0.0, 0.0), |_| Some(1.0)), Some(first));
+    }
+
+    #[test]
+    fn in_area_finds_overlapping_cells() {
+        let mut handles = Arena::new();
+        let (inside, edge, outside) = (handles.insert(()), handles.insert(()), handles.insert(()));
+
+        let mut grid = SpatialGrid::new(4.0);
+        grid.insert(inside, Vector2::new(5.0, 5.0));
+        grid.insert(edge, Vector2::new(7.9, 5.0));
+        grid.insert(outside, Vector2::new(9.0, 5.0));
+
+        let mut found = grid.in_area(Vector2::new(4.5, 4.5), Vector2::new(6.0, 6.0));
+        found.sort();
+        assert_eq!(found, vec![inside, edge]);
+
+        grid.remove(inside);
+        assert_eq!(grid.in_area(Vector2::new(4.5, 4.5), Vector2::new(6.0, 6.0)), vec![edge]);
+    }
+}
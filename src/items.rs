@@ -1,7 +1,198 @@
+use std::cmp::Ordering;
+use std::io::{self, Read, Write};
 use cgmath::{Vector2, EuclideanVector};
+use arena::{self, Arena, Handle};
+use grid::SpatialGrid;
+use save::{SaveWriter, SaveReader, SaveError};
 use tiles::Tiles;
 
-#[derive(Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ItemKind {
+    Food,
+    Mineral,
+    // Left after an item rots away, robots clear it up
+    Residue,
+    // A broken down robot, robots bring it to a fabricator to be repaired
+    Wreck,
+}
+
+const ITEM_KINDS: [ItemKind; 4] = [ItemKind::Food, ItemKind::Mineral, ItemKind::Residue, ItemKind::Wreck];
+
+impl ItemKind {
+    pub fn all() -> &'static [ItemKind] {
+        &ITEM_KINDS
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ItemKind::Food => "food",
+            ItemKind::Mineral => "mineral",
+            ItemKind::Residue => "residue",
+            ItemKind::Wreck => "wreck",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ItemKind> {
+        ITEM_KINDS.iter().cloned().find(|k| k.name() == name)
+    }
+
+    // Seconds it lasts while nobody claims it, None if it never rots
+    pub fn lifetime(&self) -> Option<f32> {
+        match *self {
+            ItemKind::Food => Some(60.0),
+            ItemKind::Mineral => Some(240.0),
+            ItemKind::Residue | ItemKind::Wreck => None,
+        }
+    }
+
+    pub fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_u8(*self as u8)
+    }
+
+    pub fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let v = r.read_u8()?;
+        ITEM_KINDS.get(v as usize).cloned()
+            .ok_or_else(|| SaveError::Corrupt(format!("{} isn't a valid item kind", v)))
+    }
+}
+
+// Items of each kind, like what it takes to build something
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recipe {
+    counts: Vec<u32>,
+}
+
+impl Recipe {
+    pub fn new() -> Self {
+        Recipe {
+            counts: vec![0; ITEM_KINDS.len()],
+        }
+    }
+
+    pub fn get(&self, kind: ItemKind) -> u32 {
+        self.counts[kind as usize]
+    }
+
+    pub fn set(&mut self, kind: ItemKind, count: u32) {
+        self.counts[kind as usize] = count;
+    }
+
+    pub fn total(&self) -> u32 {
+        self.counts.iter().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.iter().all(|c| *c == 0)
+    }
+
+    pub fn next_needed(&self) -> Option<ItemKind> {
+        ITEM_KINDS.iter().cloned().find(|k| self.get(*k) != 0)
+    }
+
+    // False if none of the kind was needed
+    pub fn take(&mut self, kind: ItemKind) -> bool {
+        let count = &mut self.counts[kind as usize];
+        if *count == 0 {
+            false
+        } else {
+            *count -= 1;
+            true
+        }
+    }
+
+    pub fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        for count in &self.counts {
+            w.write_u32(*count)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let mut recipe = Recipe::new();
+        for count in &mut recipe.counts {
+            *count = r.read_u32()?;
+        }
+        Ok(recipe)
+    }
+}
+
+// Items collide as circles this wide
+pub const ITEM_SIZE: f32 = 0.25;
+
+// Slowing down while sliding over something
+const ITEM_FRICTION: f32 = 4.0;
+
+// Items lying on something come to rest below this speed
+const SETTLE_SPEED: f32 = 0.05;
+
+// Rotting speed in a stockpile
+const STOCKPILE_DECAY: f32 = 0.25;
+
+// Longer updates are split up so items don't fall through each other
+const MAX_STEP: f32 = 1.0 / 30.0;
+
+// Can be changed per map
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ItemPhysics {
+    // Tiles per second per second
+    pub gravity: f32,
+    // Tiles per second
+    pub terminal_velocity: f32,
+}
+
+impl ItemPhysics {
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_f32(self.gravity)?;
+        w.write_f32(self.terminal_velocity)
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let physics = ItemPhysics {
+            gravity: r.read_f32()?,
+            terminal_velocity: r.read_f32()?,
+        };
+
+        if physics.gravity <= 0.0 || physics.terminal_velocity <= 0.0 {
+            return Err(SaveError::Corrupt("items don't fall".to_string()));
+        }
+
+        Ok(physics)
+    }
+}
+
+impl Default for ItemPhysics {
+    fn default() -> Self {
+        ItemPhysics {
+            gravity: 6.0,
+            terminal_velocity: 3.0,
+        }
+    }
+}
+
+// Outside of the map counts as solid
+fn solid_at(tiles: &Tiles, x: f32, y: f32) -> bool {
+    if x < 0.0 || y < 0.0 {
+        return true;
+    }
+
+    tiles.get(x as u32, y as u32)
+        .map(|v| v.is_solid(tiles.defs()))
+        .unwrap_or(true)
+}
+
+pub fn in_stockpile(tiles: &Tiles, position: Vector2<f32>) -> bool {
+    position.x >= 0.0 && position.y >= 0.0 && tiles.is_stockpile(position.x as u32, position.y as u32)
+}
+
+// Rotten items can't be used anymore
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Freshness {
+    Fresh,
+    Stale,
+    Rotten,
+}
+
+#[derive(Debug, Eq, PartialEq)]
 pub enum ItemState {
     Static,
     Falling,
@@ -9,152 +200,511 @@ pub enum ItemState {
 }
 
 pub struct Item {
+    pub kind: ItemKind,
     pub position: Vector2<f32>,
+    pub velocity: Vector2<f32>,
     pub lifetime: f32,
     pub state: ItemState,
     pub claimed: bool,
 }
 
 impl Item {
+    pub fn new(kind: ItemKind, position: Vector2<f32>) -> Self {
+        Item {
+            kind: kind,
+            position: position,
+            velocity: Vector2::new(0.0, 0.0),
+            lifetime: kind.lifetime().unwrap_or(0.0),
+            state: ItemState::Falling,
+            claimed: false,
+        }
+    }
+
     pub fn position(&self) -> Vector2<f32> {
         self.position
     }
+
+    pub fn freshness(&self) -> Freshness {
+        let lifetime = if let Some(lifetime) = self.kind.lifetime() {
+            lifetime
+        } else {
+            return Freshness::Fresh;
+        };
+
+        // Go by how much of its lifetime the item has left
+        let left = self.lifetime / lifetime;
+        if left > 0.5 {
+            Freshness::Fresh
+        } else if left > 0.2 {
+            Freshness::Stale
+        } else {
+            Freshness::Rotten
+        }
+    }
+
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        self.kind.write_save(w)?;
+        w.write_vec2_f32(self.position)?;
+        w.write_vec2_f32(self.velocity)?;
+        w.write_f32(self.lifetime)?;
+        w.write_u8(match self.state {
+            ItemState::Static => 0,
+            ItemState::Falling => 1,
+            ItemState::Carried => 2,
+        })?;
+        w.write_bool(self.claimed)
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let kind = ItemKind::read_save(r)?;
+        let position = r.read_vec2_f32()?;
+        let velocity = r.read_vec2_f32()?;
+        let lifetime = r.read_f32()?;
+        let state = match r.read_u8()? {
+            0 => ItemState::Static,
+            1 => ItemState::Falling,
+            2 => ItemState::Carried,
+            v => return Err(SaveError::Corrupt(format!("{} isn't a valid item state", v))),
+        };
+        let claimed = r.read_bool()?;
+
+        Ok(Item {
+            kind: kind,
+            position: position,
+            velocity: velocity,
+            lifetime: lifetime,
+            state: state,
+            claimed: claimed,
+        })
+    }
 }
 
+// In tiles
+const GRID_CELL_SIZE: f32 = 4.0;
+
 pub struct Items {
-    items: Vec<Option<Item>>,
+    items: Arena<Item>,
+    grid: SpatialGrid, // where items were at the end of the last update
+    physics: ItemPhysics,
 }
 
 impl Items {
-    pub fn new() -> Self {
+    pub fn new(physics: ItemPhysics) -> Self {
         Items {
-            items: Vec::new(),
+            items: Arena::new(),
+            grid: SpatialGrid::new(GRID_CELL_SIZE),
+            physics: physics,
+        }
+    }
+
+    pub fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        self.physics.write_save(w)?;
+        self.items.write_save(w, |item, w| item.write_save(w))
+    }
+
+    pub fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let physics = ItemPhysics::read_save(r)?;
+        let items = Arena::read_save(r, Item::read_save)?;
+
+        // The grid can be built up again from the positions
+        let mut grid = SpatialGrid::new(GRID_CELL_SIZE);
+        for (id, item) in items.iter() {
+            grid.insert(id, item.position);
         }
+
+        Ok(Items {
+            items: items,
+            grid: grid,
+            physics: physics,
+        })
+    }
+
+    pub fn iter<'a>(&'a self) -> arena::Iter<'a, Item> {
+        self.items.iter()
     }
 
     pub fn for_each<F: FnMut(&Item)>(&self, mut f: F) {
-        for item in &self.items {
-            if let &Some(ref item) = item {
-                f(item);
-            }
+        for (_, item) in self.items.iter() {
+            f(item);
         }
     }
 
     pub fn for_each_mut<F: FnMut(&mut Item)>(&mut self, mut f: F) {
-        for item in &mut self.items {
-            if let &mut Some(ref mut item) = item {
-                f(item);
-            }
+        for (_, item) in self.items.iter_mut() {
+            f(item);
         }
     }
 
-    /*pub fn get(&self, id: u32) -> Option<&Item> {
-        self.items.get(id as usize).and_then(|v| v.as_ref())
-    }*/
+    pub fn get(&self, id: Handle) -> Option<&Item> {
+        self.items.get(id)
+    }
 
-    pub fn get_mut(&mut self, id: u32) -> Option<&mut Item> {
-        self.items.get_mut(id as usize).and_then(|v| v.as_mut())
+    pub fn get_mut(&mut self, id: Handle) -> Option<&mut Item> {
+        self.items.get_mut(id)
     }
 
-    pub fn remove(&mut self, id: u32) {
-        self.items[id as usize] = None;
+    pub fn remove(&mut self, id: Handle) {
+        self.items.remove(id);
+        self.grid.remove(id);
         println!("Item {} was removed", id);
     }
 
     pub fn remove_if<F: Fn(&Item) -> bool>(&mut self, f: F) {
-        for i in 0..self.items.len() {
-            let item = &mut self.items[i];
-
-            let mut kill = false;
-            if let &mut Some(ref mut item) = item {
-                kill = f(item);
-            }
-            if kill {
-                println!("Item {} was removed", i);
-                *item = None;
-            }
+        for id in self.items.remove_if(f) {
+            self.grid.remove(id);
+            println!("Item {} was removed", id);
         }
     }
 
-    pub fn add(&mut self, item: Item) {
-        // Find an empty slot
-        for item_o in &mut self.items {
-            if item_o.is_some() {
-                continue;
-            }
+    pub fn add(&mut self, item: Item) -> Handle {
+        let position = item.position;
+        let id = self.items.insert(item);
+        self.grid.insert(id, position);
+        id
+    }
 
-            // Found a slot, add it and return
-            *item_o =  Some(item);
-            return;
-        }
+    pub fn in_area(&self, min: Vector2<f32>, max: Vector2<f32>) -> Vec<Handle> {
+        let mut found: Vec<_> = self.grid.in_area(min, max).into_iter()
+            .filter(|id| {
+                let pos = self.items.get(*id).unwrap().position;
+                pos.x >= min.x && pos.y >= min.y && pos.x <= max.x && pos.y <= max.y
+            })
+            .collect();
+        found.sort();
+        found
+    }
 
-        // Couldn't find one, add to end
-        self.items.push(Some(item));
+    pub fn physics(&self) -> ItemPhysics {
+        self.physics
     }
 
     pub fn update(&mut self, tiles: &Tiles, delta: f32) {
-        self.for_each_mut(|item| {
-            // Make the item fall down, but only if it's not carried
-            if item.state != ItemState::Carried {
-                // Get the new position for the item
-                let mut pos = item.position;
-                pos[1] -= 1.5 * delta;
-
-                // Make sure the new position doesn't collide with any tiles
-                if tiles.get(pos[0] as u32, (pos[1] - 0.1) as u32)
-                    .map(|v| v.class())
-                    .unwrap_or(0) != 0 {
-                    // We collided, switch to static mode
-                    item.state = ItemState::Static;
-                } else {
-                    // Apply the change
-                    item.position = pos;
-                    item.state = ItemState::Falling;
-                }
+        // Move the items in small enough steps that they can't pass through each other
+        let steps = (delta / MAX_STEP).ceil().max(1.0);
+        for _ in 0..steps as u32 {
+            self.update_physics(tiles, delta / steps);
+        }
+
+        for (id, item) in self.items.iter_mut() {
+            // Claimed items are about to be used, they don't rot until they're let go of again
+            if item.kind.lifetime().is_none() || item.claimed {
+                continue;
             }
 
-            // Update the item's lifetime, but only if it's not claimed, reset its lifetime otherwise
-            if item.claimed {
-                item.lifetime = 60.0;
+            if in_stockpile(tiles, item.position) {
+                item.lifetime -= delta * STOCKPILE_DECAY;
             } else {
                 item.lifetime -= delta;
             }
-        });
 
-        // Remove all items that have a lifetime of or less than zero
-        self.remove_if(|item| item.lifetime <= 0.0);
+            // Items that rotted away all the way leave residue behind
+            if item.lifetime <= 0.0 {
+                item.kind = ItemKind::Residue;
+                item.lifetime = 0.0;
+                println!("Item {} rotted away", id);
+            }
+        }
+
+        // Keep the grid up to date with everything that moved, also when moved by robots
+        for (id, item) in self.items.iter() {
+            self.grid.update(id, item.position);
+        }
     }
 
-    pub fn claim_resource(&mut self, near: Vector2<f32>) -> Option<u32> {
-        let mut closest = (None, 1000000.0);
+    fn update_physics(&mut self, tiles: &Tiles, delta: f32) {
+        // Go from the bottom up, so items have already settled when others land on them
+        let mut order: Vec<_> = self.items.iter()
+            .filter(|&(_, item)| item.state != ItemState::Carried)
+            .map(|(id, item)| (item.position.y, id))
+            .collect();
+        // Positions are always finite, saves and maps with anything else don't load
+        order.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
 
-        // Find the first unclaimed non-falling resource
-        for i in 0..self.items.len() {
-            let item_o = &self.items[i];
-            if item_o.is_none() {
-                continue;
+        let half = ITEM_SIZE / 2.0;
+        let reach = Vector2::new(ITEM_SIZE * 2.0, ITEM_SIZE * 2.0);
+        for (_, id) in order {
+            let start = self.items.get(id).unwrap().position;
+            let mut velocity = self.items.get(id).unwrap().velocity;
+
+            // Accelerate downwards, up to the maximum
+            velocity.y = (velocity.y - self.physics.gravity * delta).max(-self.physics.terminal_velocity);
+            let mut pos = start + velocity * delta;
+            let mut supported = false;
+
+            // Push the item out of the items it's lying on or next to
+            for other_id in self.grid.in_area(pos - reach, pos + reach) {
+                let other = self.items.get(other_id).unwrap();
+                if other_id == id || other.state == ItemState::Carried || other.position.y > pos.y {
+                    continue;
+                }
+
+                let difference = pos - other.position;
+                let distance = difference.magnitude2().sqrt();
+                if distance >= ITEM_SIZE {
+                    continue;
+                }
+
+                let normal = if distance > 0.0 { difference / distance } else { Vector2::new(0.0, 1.0) };
+                pos = other.position + normal * ITEM_SIZE;
+                if normal.y > 0.5 {
+                    supported = true;
+                }
             }
 
-            // If the item's falling or is already claimed, skip it
-            let item = item_o.as_ref().unwrap();
-            if item.state != ItemState::Static || item.claimed {
-                continue;
+            // Land flush on the tile below the center, so items hanging over an edge fall off
+            let below = pos.y - half - 0.001;
+            if solid_at(tiles, pos.x, below) {
+                pos.y = below.floor() + 1.0 + half;
+                supported = true;
+            }
+
+            // Keep out of the tiles on either side
+            if solid_at(tiles, pos.x - half, pos.y) {
+                pos.x = (pos.x - half).floor() + 1.0 + half;
+            }
+            if solid_at(tiles, pos.x + half, pos.y) {
+                pos.x = (pos.x + half).floor() - half;
             }
 
-            // This one might be possible, get the squared distance
-            let distance = (near - item.position()).magnitude2();
-            if distance < closest.1 {
-                closest = (Some(i), distance);
+            // How far the item actually got is its new velocity, so being pushed back by what
+            // it's lying on or wedged against stops it. Items never get thrown upwards though.
+            velocity = (pos - start) / delta;
+            velocity.y = velocity.y.min(0.0);
+            if velocity.magnitude2() > self.physics.terminal_velocity * self.physics.terminal_velocity {
+                velocity = velocity.normalize() * self.physics.terminal_velocity;
             }
+
+            // Slow down when sliding over something, and come to rest when slow enough
+            let item = self.items.get_mut(id).unwrap();
+            if supported {
+                velocity.x -= velocity.x * (ITEM_FRICTION * delta).min(1.0);
+            }
+            if supported && velocity.magnitude2() < SETTLE_SPEED * SETTLE_SPEED {
+                item.velocity = Vector2::new(0.0, 0.0);
+                item.state = ItemState::Static;
+            } else {
+                item.velocity = velocity;
+                item.state = ItemState::Falling;
+            }
+            item.position = pos;
+
+            // Items further up need to find this one where it is now
+            self.grid.update(id, pos);
         }
+    }
+
+    // Stockpiled items first, loose ones only if there are none
+    pub fn nearest_unclaimed(&self, near: Vector2<f32>, kind: ItemKind, tiles: &Tiles, skip: &[Handle])
+        -> Option<Handle>
+    {
+        let items = &self.items;
+        let usable = |id: Handle| {
+            let item = items.get(id).unwrap();
+
+            // If the item's falling, is already claimed, has rotted, isn't what we need or we were
+            // asked to leave it alone, skip it
+            item.state == ItemState::Static && !item.claimed && item.kind == kind &&
+                item.freshness() != Freshness::Rotten && !skip.contains(&id)
+        };
+
+        // Look through the stockpiles first, only use loose items if there's nothing in them
+        self.grid.nearest(near, |id| {
+            if usable(id) && in_stockpile(tiles, items.get(id).unwrap().position) {
+                Some((near - items.get(id).unwrap().position()).magnitude2())
+            } else {
+                None
+            }
+        }).or_else(|| self.grid.nearest(near, |id| {
+            if usable(id) {
+                Some((near - items.get(id).unwrap().position()).magnitude2())
+            } else {
+                None
+            }
+        }))
+    }
+
+    pub fn claim_resource(&mut self, near: Vector2<f32>, kind: ItemKind, tiles: &Tiles, skip: &[Handle])
+        -> Option<Handle>
+    {
+        let closest = self.nearest_unclaimed(near, kind, tiles, skip);
 
         // If we found one, claim it
-        if let Some(item) = closest.0 {
-            let mut item = &mut self.items[item].as_mut().unwrap();
-            item.claimed = true;
+        if let Some(id) = closest {
+            self.items.get_mut(id).unwrap().claimed = true;
         }
 
         // Return what we found
-        closest.0.map(|v| v as u32)
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector2;
+    use arena::Handle;
+    use tiles::Tiles;
+    use super::{Freshness, Item, ItemKind, ItemPhysics, ItemState, Items, ITEM_SIZE, STOCKPILE_DECAY};
+
+    fn resting_food(items: &mut Items, tiles: &Tiles) -> Handle {
+        let id = items.add(Item::new(ItemKind::Food, Vector2::new(1.5, 1.5)));
+        for _ in 0..20 {
+            items.update(tiles, 0.1);
+        }
+        assert_eq!(items.get(id).unwrap().state, ItemState::Static);
+        id
+    }
+
+    fn settle(items: &mut Items, tiles: &Tiles, seconds: u32) {
+        for _ in 0..seconds * 10 {
+            items.update(tiles, 0.1);
+        }
+    }
+
+    #[test]
+    fn items_land_flush_on_tiles() {
+        let tiles = Tiles::from_rows(&["...", "...", "###"]);
+        let mut items = Items::new(ItemPhysics::default());
+        let id = items.add(Item::new(ItemKind::Food, Vector2::new(1.5, 2.5)));
+        settle(&mut items, &tiles, 3);
+
+        let item = items.get(id).unwrap();
+        assert_eq!(item.state, ItemState::Static);
+        assert_eq!(item.position, Vector2::new(1.5, 1.0 + ITEM_SIZE / 2.0));
+    }
+
+    #[test]
+    fn items_stack_on_each_other() {
+        let tiles = Tiles::from_rows(&["...", "...", "...", "...", "...", "...", "...", "...", "###"]);
+        let mut items = Items::new(ItemPhysics::default());
+        let lower = items.add(Item::new(ItemKind::Food, Vector2::new(1.5, 6.5)));
+        let upper = items.add(Item::new(ItemKind::Mineral, Vector2::new(1.5, 7.5)));
+
+        // All in one go, so the lower one crosses into another grid cell halfway through
+        items.update(&tiles, 4.0);
+
+        let lower = items.get(lower).unwrap();
+        let upper = items.get(upper).unwrap();
+        assert_eq!((&lower.state, &upper.state), (&ItemState::Static, &ItemState::Static));
+        assert_eq!(lower.position.y, 1.0 + ITEM_SIZE / 2.0);
+        assert!((upper.position.y - (lower.position.y + ITEM_SIZE)).abs() < 0.001, "{:?}", upper.position);
+    }
+
+    #[test]
+    fn items_slide_off_edges_and_each_other() {
+        let tiles = Tiles::from_rows(&["....", "#...", "####"]);
+        let mut items = Items::new(ItemPhysics::default());
+
+        // Hanging over the edge of a ledge with its center past it
+        let hanging = items.add(Item::new(ItemKind::Food, Vector2::new(1.05, 2.5)));
+        settle(&mut items, &tiles, 3);
+        let position = items.get(hanging).unwrap().position;
+        assert_eq!(position.y, 1.0 + ITEM_SIZE / 2.0);
+        assert!(position.x >= 1.0 + ITEM_SIZE / 2.0, "{:?}", position);
+
+        // Landing off center on another item
+        let resting = items.add(Item::new(ItemKind::Food, Vector2::new(2.5, 1.5)));
+        settle(&mut items, &tiles, 3);
+        let sliding = items.add(Item::new(ItemKind::Food, Vector2::new(2.6, 1.8)));
+        settle(&mut items, &tiles, 3);
+        let resting = items.get(resting).unwrap().position;
+        let sliding = items.get(sliding).unwrap();
+        assert_eq!(sliding.state, ItemState::Static);
+        assert_eq!(sliding.position.y, 1.0 + ITEM_SIZE / 2.0);
+        assert!(sliding.position.x - resting.x >= ITEM_SIZE - 0.001, "{:?}", sliding.position);
+    }
+
+    #[test]
+    fn falling_is_capped_at_terminal_velocity() {
+        let rows = vec!["."; 30].into_iter().chain(Some("#")).collect::<Vec<_>>();
+        let tiles = Tiles::from_rows(&rows);
+        let physics = ItemPhysics::default();
+        let mut items = Items::new(physics);
+        let id = items.add(Item::new(ItemKind::Food, Vector2::new(0.5, 29.5)));
+
+        for _ in 0..20 {
+            items.update(&tiles, 0.1);
+            assert!(items.get(id).unwrap().velocity.y >= -physics.terminal_velocity);
+        }
+        assert_eq!(items.get(id).unwrap().velocity.y, -physics.terminal_velocity);
+    }
+
+    #[test]
+    fn stockpiled_items_are_used_first() {
+        let mut tiles = Tiles::from_rows(&["..........", "##########"]);
+        tiles.get_mut(9, 1).unwrap().set_stockpile(true);
+        let mut items = Items::new(ItemPhysics::default());
+        let loose = items.add(Item::new(ItemKind::Food, Vector2::new(1.5, 1.5)));
+        let stockpiled = items.add(Item::new(ItemKind::Food, Vector2::new(9.5, 1.5)));
+        settle(&mut items, &tiles, 2);
+
+        // Even when a loose one is much closer
+        let near = Vector2::new(0.5, 1.5);
+        assert_eq!(items.nearest_unclaimed(near, ItemKind::Food, &tiles, &[]), Some(stockpiled));
+        assert_eq!(items.nearest_unclaimed(near, ItemKind::Food, &tiles, &[stockpiled]), Some(loose));
+        assert_eq!(items.nearest_unclaimed(near, ItemKind::Mineral, &tiles, &[]), None);
+
+        // Loose items are only the fallback once the stockpile's empty
+        assert_eq!(items.claim_resource(near, ItemKind::Food, &tiles, &[]), Some(stockpiled));
+        assert_eq!(items.claim_resource(near, ItemKind::Food, &tiles, &[]), Some(loose));
+        assert_eq!(items.claim_resource(near, ItemKind::Food, &tiles, &[]), None);
+    }
+
+    #[test]
+    fn stockpiles_slow_down_rotting() {
+        let mut tiles = Tiles::from_rows(&["...", "###"]);
+        tiles.get_mut(0, 1).unwrap().set_stockpile(true);
+        let mut items = Items::new(ItemPhysics::default());
+        let loose = items.add(Item::new(ItemKind::Food, Vector2::new(2.5, 1.5)));
+        let stockpiled = items.add(Item::new(ItemKind::Food, Vector2::new(0.5, 1.5)));
+        settle(&mut items, &tiles, 2);
+        let loose_before = items.get(loose).unwrap().lifetime;
+        let stockpiled_before = items.get(stockpiled).unwrap().lifetime;
+
+        items.update(&tiles, 10.0);
+        assert_eq!(items.get(loose).unwrap().lifetime, loose_before - 10.0);
+        assert_eq!(items.get(stockpiled).unwrap().lifetime, stockpiled_before - 10.0 * STOCKPILE_DECAY);
+    }
+
+    #[test]
+    fn unclaimed_items_rot_into_residue() {
+        let tiles = Tiles::from_rows(&["...", "###"]);
+        let mut items = Items::new(ItemPhysics::default());
+        let id = resting_food(&mut items, &tiles);
+        let lifetime = ItemKind::Food.lifetime().unwrap();
+
+        let mut seen = Vec::new();
+        for _ in 0..(lifetime as u32 + 1) {
+            let item = items.get(id).unwrap();
+            if item.kind == ItemKind::Residue {
+                break;
+            }
+            if seen.last() != Some(&item.freshness()) {
+                seen.push(item.freshness());
+            }
+            items.update(&tiles, 1.0);
+        }
+
+        assert_eq!(seen, vec![Freshness::Fresh, Freshness::Stale, Freshness::Rotten]);
+        assert_eq!(items.get(id).unwrap().kind, ItemKind::Residue);
+    }
+
+    #[test]
+    fn claimed_items_keep_their_age() {
+        let tiles = Tiles::from_rows(&["...", "###"]);
+        let mut items = Items::new(ItemPhysics::default());
+        let id = resting_food(&mut items, &tiles);
+
+        items.update(&tiles, 10.0);
+        let lifetime = items.get(id).unwrap().lifetime;
+
+        // Claiming pauses the decay without making it fresh again
+        items.get_mut(id).unwrap().claimed = true;
+        items.update(&tiles, 1000.0);
+        assert_eq!(items.get(id).unwrap().lifetime, lifetime);
+
+        // Letting go picks up where it left off
+        items.get_mut(id).unwrap().claimed = false;
+        items.update(&tiles, 10.0);
+        assert_eq!(items.get(id).unwrap().lifetime, lifetime - 10.0);
     }
 }
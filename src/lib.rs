@@ -0,0 +1,38 @@
+// Clippy's suggestions for newer or terser forms don't fit the way the code is written
+#![allow(unknown_lints)]
+#![allow(
+    clippy::assign_op_pattern,
+    clippy::cast_abs_to_unsigned,
+    clippy::len_without_is_empty,
+    clippy::legacy_numeric_constants,
+    clippy::manual_clamp,
+    clippy::match_like_matches_macro,
+    clippy::mem_replace_with_default,
+    clippy::new_without_default,
+    clippy::question_mark,
+    clippy::redundant_field_names,
+    clippy::redundant_pattern_matching,
+    clippy::redundant_static_lifetimes,
+    clippy::unwrap_or_default,
+)]
+
+extern crate tiled;
+extern crate rand;
+#[macro_use] extern crate enum_primitive;
+extern crate cgmath;
+extern crate xml;
+
+pub mod arena;
+mod grid;
+pub mod items;
+pub mod map;
+pub mod model;
+pub mod objects;
+mod pathfinding;
+pub mod replay;
+pub mod rng;
+pub mod robots;
+pub mod save;
+pub mod tile_defs;
+pub mod tiles;
+pub mod tmx;
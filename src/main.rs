@@ -1,40 +1,86 @@
-extern crate tiled;
-extern crate rand;
-#[macro_use] extern crate enum_primitive;
+// The same clippy lints as the library are left off
+#![allow(clippy::redundant_field_names, clippy::redundant_static_lifetimes)]
+
 extern crate cgmath;
 extern crate tungsten;
 extern crate tungsten_glium2d;
+extern crate shift_inc;
 
-mod items;
-mod map;
-mod model;
-mod robots;
-mod tiles;
 mod view;
 
+use std::env;
 use std::fs::File;
 use std::path::Path;
 use cgmath::Vector2;
 use tungsten::{Framework, EventDispatcher, UpdateEvent};
 use tungsten_glium2d::{Frontend2D, CloseRequestEvent, KeyboardInputEvent, Key, ElementState, MouseMoveEvent, MouseButton, MouseButtonEvent};
-use model::{GameModel, GameButton};
+use shift_inc::model::{GameModel, GameButton};
+use shift_inc::rng;
 use view::View;
 
-fn close_request_handler(model: &mut GameModel, _event: &CloseRequestEvent) {
+const RECORDING_PATH: &'static str = "recording.txt";
+
+// Writes out the recording if there is one, returns if there was
+fn write_recording(model: &mut GameModel) -> bool {
+    if let Some(recording) = model.finish_recording() {
+        match File::create(RECORDING_PATH).and_then(|file| recording.write(file)) {
+            Ok(_) => println!("Recording written to {}", RECORDING_PATH),
+            Err(e) => println!("Error writing recording to {}: {}", RECORDING_PATH, e),
+        }
+        true
+    } else {
+        false
+    }
+}
+
+fn close(model: &mut GameModel) {
+    write_recording(model);
     model.close();
 }
 
+fn close_request_handler(model: &mut GameModel, _event: &CloseRequestEvent) {
+    close(model);
+}
+
 fn update_handler(model: &mut GameModel, event: &UpdateEvent) {
     model.update(event.delta);
 }
 
+const SAVE_PATH: &'static str = "save.dat";
+
+fn quick_save(model: &GameModel) {
+    let result = File::create(SAVE_PATH).and_then(|file| model.save(file));
+    match result {
+        Ok(_) => println!("Saved to {}", SAVE_PATH),
+        Err(e) => println!("Error saving to {}: {}", SAVE_PATH, e),
+    }
+}
+
+fn quick_load(model: &mut GameModel) {
+    let result = File::open(SAVE_PATH).map_err(|e| e.into()).and_then(GameModel::restore);
+    match result {
+        Ok(loaded) => {
+            // Recordings are replayed from the start of the map, they can't jump to a save
+            if write_recording(model) {
+                println!("Stopped recording, loading a save can't be replayed");
+            }
+
+            *model = loaded;
+            println!("Loaded from {}", SAVE_PATH);
+        },
+        Err(e) => println!("Error loading from {}: {}", SAVE_PATH, e),
+    }
+}
+
 fn keyboard_handler(model: &mut GameModel, event: &KeyboardInputEvent) {
     let pressed = event.state == ElementState::Pressed;
 
-    // Check for the escape key
+    // Check for the escape and save keys
     if pressed {
         match event.key {
-            Key::Escape => model.close(),
+            Key::Escape => close(model),
+            Key::F5 => quick_save(model),
+            Key::F9 => quick_load(model),
             _ => ()
         }
     }
@@ -45,6 +91,13 @@ fn keyboard_handler(model: &mut GameModel, event: &KeyboardInputEvent) {
         Key::A => model.handle_keychange(GameButton::CameraLeft, pressed),
         Key::S => model.handle_keychange(GameButton::CameraDown, pressed),
         Key::D => model.handle_keychange(GameButton::CameraRight, pressed),
+        Key::E => model.handle_keychange(GameButton::NextStructure, pressed),
+        Key::Q => model.handle_keychange(GameButton::PreviousStructure, pressed),
+        Key::P => model.handle_keychange(GameButton::BumpPriority, pressed),
+        Key::Z => model.handle_keychange(GameButton::Stockpile, pressed),
+        Key::F => model.handle_keychange(GameButton::QueueRobot, pressed),
+        Key::Equals => model.handle_keychange(GameButton::RaiseRobotCap, pressed),
+        Key::Minus => model.handle_keychange(GameButton::LowerRobotCap, pressed),
         _ => ()
     }
 }
@@ -58,14 +111,42 @@ fn mouse_button_handler(model: &mut GameModel, event: &MouseButtonEvent) {
 
     match event.button {
         MouseButton::Left => model.handle_keychange(GameButton::Interact, pressed),
+        MouseButton::Right => model.handle_keychange(GameButton::Deconstruct, pressed),
         _ => ()
     }
 }
 
+// From `--seed <n>`, or a random one
+fn seed_from_args() -> u64 {
+    let args: Vec<String> = env::args().collect();
+    if let Some(i) = args.iter().position(|a| a == "--seed") {
+        if let Some(seed) = args.get(i + 1).and_then(|v| v.parse().ok()) {
+            return seed;
+        }
+        println!("--seed needs a number, using a random seed instead");
+    }
+
+    rng::random_seed()
+}
+
 fn main() {
-    let mut file = File::open(&Path::new("assets/map.tmx")).unwrap();
-    let map = tiled::parse(&mut file).unwrap();
-    let model = GameModel::new(map);
+    let seed = seed_from_args();
+    println!("Using seed {}", seed);
+
+    let path = Path::new("assets/map.tmx");
+    let mut model = match GameModel::load(path, seed) {
+        Ok(model) => model,
+        Err(e) => {
+            println!("Error loading map {}: {}", path.display(), e);
+            ::std::process::exit(1);
+        }
+    };
+
+    // Record all input if asked to, so the session can be replayed with the headless runner
+    if env::args().any(|a| a == "--record") {
+        model.start_recording(&path.to_string_lossy());
+        println!("Recording input to {}", RECORDING_PATH);
+    }
 
     let mut event_dispatcher = EventDispatcher::new();
     event_dispatcher.add_handler(close_request_handler);
@@ -75,7 +156,7 @@ fn main() {
     event_dispatcher.add_handler(mouse_button_handler);
 
     let mut frontend = Frontend2D::new();
-    let view = View::new(&mut frontend);
+    let view = View::new(&mut frontend, &model);
     frontend.set_view(view);
 
     let framework = Framework::new(model, frontend, event_dispatcher);
@@ -1,37 +1,355 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
 use cgmath::Vector2;
-use tiled::{Map, Object};
-use rand::{StdRng, Rng};
-use items::{Item, Items, ItemState};
-use robots::{Robots, Robot, WorkQueue, WorkEntry};
+use tiled::TiledError;
+use rand::Rng;
+use arena::Handle;
+use rng::GameRng;
+use items::{self, Freshness, Item, ItemKind, ItemPhysics, ItemState, Items, Recipe};
+use pathfinding;
+use robots::{Robots, Robot, WorkQueue, WorkEntry, WorkKind};
 use tiles::Tiles;
+use tile_defs::{Fabrication, TileDefs};
+use tmx::MapFile;
+use objects::{self, ObjectKind, MapObject};
+use save::{SaveWriter, SaveReader, SaveError};
 
 struct FoodSpawner {
     position: [f32; 2],
     size: [f32; 2],
+    kind: ItemKind,
+    // Per minute
+    rate: f32,
+    // Items per spawn
+    burst: u32,
+    accum: f32,
 }
 
 impl FoodSpawner {
-    fn new(position: [f32; 2], size: [f32; 2]) -> Self {
+    fn new(object: &MapObject, kind: ItemKind, rate: f32, burst: u32) -> Self {
         FoodSpawner {
-            position: position,
-            size: size,
+            position: object.position.into(),
+            size: object.size.into(),
+            kind: kind,
+            rate: rate,
+            burst: burst,
+            accum: 0.0,
         }
     }
 
-    fn spawn(&self, rng: &mut StdRng) -> Item {
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_vec2_f32(self.position.into())?;
+        w.write_vec2_f32(self.size.into())?;
+        self.kind.write_save(w)?;
+        w.write_f32(self.rate)?;
+        w.write_u32(self.burst)?;
+        w.write_f32(self.accum)
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let position = r.read_vec2_f32()?;
+        let size = r.read_vec2_f32()?;
+        let kind = ItemKind::read_save(r)?;
+        let rate = r.read_f32()?;
+        let burst = r.read_u32()?;
+        let accum = r.read_f32()?;
+
+        Ok(FoodSpawner {
+            position: position.into(),
+            size: size.into(),
+            kind: kind,
+            rate: rate,
+            burst: burst,
+            accum: accum,
+        })
+    }
+
+    fn update(&mut self, delta: f32, items: &mut Items, rng: &mut GameRng) {
+        if self.rate <= 0.0 {
+            return;
+        }
+
+        let interval = 60.0 / self.rate;
+        self.accum += delta;
+        while self.accum > interval {
+            self.accum -= interval;
+            for _ in 0..self.burst {
+                items.add(self.spawn(rng));
+            }
+        }
+    }
+
+    fn spawn(&self, rng: &mut GameRng) -> Item {
         let x = rng.gen_range(self.position[0], self.position[0] + self.size[0]);
         let y = rng.gen_range(self.position[1], self.position[1] + self.size[1]);
-        println!("Spawning food at {}, {}", x, y);
+        println!("Spawning {} at {}, {}", self.kind.name(), x, y);
+
+        Item::new(self.kind, Vector2::new(x, y))
+    }
+}
 
-        Item {
-            position: Vector2::new(x, y),
-            lifetime: 60.0,
-            state: ItemState::Falling,
-            claimed: false,
+#[derive(Debug)]
+pub enum MapLoadError {
+    Io(io::Error),
+    Tiled(TiledError),
+    Xml(String),
+    InvalidTileset(String),
+    InvalidTileProperty { tile: u32, property: String, value: String },
+    InvalidMapProperty { property: String, value: String },
+    InvalidObjectProperty { group: String, index: usize, property: String, value: String },
+    MissingLayer(String),
+    UnexpectedLayer(String),
+    LayerSizeMismatch { layer: String, expected: (u32, u32), found: (u32, u32) },
+    UnknownTile { layer: String, gid: u32, column: u32, row: u32 },
+    UnsupportedObject { group: String, index: usize },
+    UnknownObjectType { group: String, index: usize, object_type: String },
+    BlockedObject { group: String, index: usize },
+    NoFoodSpawners,
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapLoadError::Io(ref e) =>
+                write!(f, "Couldn't read map file: {}", e),
+            MapLoadError::Tiled(ref e) =>
+                write!(f, "Couldn't parse map file: {:?}", e),
+            MapLoadError::Xml(ref reason) =>
+                write!(f, "Couldn't parse map file: {}", reason),
+            MapLoadError::InvalidTileset(ref reason) =>
+                write!(f, "Map's tileset is invalid: {}", reason),
+            MapLoadError::InvalidTileProperty { tile, ref property, ref value } =>
+                write!(f, "Tileset tile {} has invalid value \"{}\" for property \"{}\"",
+                    tile, value, property),
+            MapLoadError::InvalidMapProperty { ref property, ref value } =>
+                write!(f, "Map has invalid value \"{}\" for property \"{}\"", value, property),
+            MapLoadError::InvalidObjectProperty { ref group, index, ref property, ref value } =>
+                write!(f, "Object {} in object group \"{}\" has invalid value \"{}\" for property \"{}\"",
+                    index, group, value, property),
+            MapLoadError::MissingLayer(ref name) =>
+                write!(f, "Map is missing the \"{}\" tile layer", name),
+            MapLoadError::UnexpectedLayer(ref name) =>
+                write!(f, "Map has an unexpected tile layer \"{}\"", name),
+            MapLoadError::LayerSizeMismatch { ref layer, expected, found } =>
+                write!(f, "Tile layer \"{}\" is {}x{} but the map is {}x{}",
+                    layer, found.0, found.1, expected.0, expected.1),
+            MapLoadError::UnknownTile { ref layer, gid, column, row } =>
+                write!(f, "Tile layer \"{}\" has unknown tile GID {} at column {}, row {}",
+                    layer, gid, column, row),
+            MapLoadError::UnsupportedObject { ref group, index } =>
+                write!(f, "Object {} in object group \"{}\" isn't a rectangle, only rectangles are supported",
+                    index, group),
+            MapLoadError::UnknownObjectType { ref group, index, ref object_type } =>
+                write!(f, "Object {} in object group \"{}\" has unknown type \"{}\"",
+                    index, group, object_type),
+            MapLoadError::BlockedObject { ref group, index } =>
+                write!(f, "Object {} in object group \"{}\" places robots inside a solid tile",
+                    index, group),
+            MapLoadError::NoFoodSpawners =>
+                write!(f, "Map has no food spawners, add a rectangle with the \"FoodSpawn\" type"),
         }
     }
 }
 
+impl Error for MapLoadError {
+    fn description(&self) -> &str {
+        match *self {
+            MapLoadError::Io(_) => "couldn't read map file",
+            MapLoadError::Tiled(_) => "couldn't parse map file",
+            MapLoadError::Xml(_) => "couldn't parse map file",
+            MapLoadError::InvalidTileset(_) => "invalid tileset",
+            MapLoadError::InvalidTileProperty { .. } => "invalid tile property",
+            MapLoadError::InvalidMapProperty { .. } => "invalid map property",
+            MapLoadError::InvalidObjectProperty { .. } => "invalid object property",
+            MapLoadError::MissingLayer(_) => "missing tile layer",
+            MapLoadError::UnexpectedLayer(_) => "unexpected tile layer",
+            MapLoadError::LayerSizeMismatch { .. } => "tile layer size doesn't match map",
+            MapLoadError::UnknownTile { .. } => "unknown tile",
+            MapLoadError::UnsupportedObject { .. } => "unsupported object",
+            MapLoadError::UnknownObjectType { .. } => "unknown object type",
+            MapLoadError::BlockedObject { .. } => "object places robots inside a solid tile",
+            MapLoadError::NoFoodSpawners => "no food spawners",
+        }
+    }
+}
+
+impl From<io::Error> for MapLoadError {
+    fn from(e: io::Error) -> Self {
+        MapLoadError::Io(e)
+    }
+}
+
+impl From<TiledError> for MapLoadError {
+    fn from(e: TiledError) -> Self {
+        MapLoadError::Tiled(e)
+    }
+}
+
+fn map_property<T: FromStr>(file: &MapFile, name: &str, default: T) -> Result<T, MapLoadError> {
+    match file.properties.get(name) {
+        Some(value) => value.parse().map_err(|_| MapLoadError::InvalidMapProperty {
+            property: name.to_string(),
+            value: value.clone(),
+        }),
+        None => Ok(default),
+    }
+}
+
+fn positive_map_property(file: &MapFile, name: &str, default: f32) -> Result<f32, MapLoadError> {
+    let value = map_property(file, name, default)?;
+    if value.is_finite() && value > 0.0 {
+        Ok(value)
+    } else {
+        Err(MapLoadError::InvalidMapProperty {
+            property: name.to_string(),
+            value: file.properties.get(name).cloned().unwrap_or_else(|| value.to_string()),
+        })
+    }
+}
+
+// Outside of the map isn't
+fn passable_at(tiles: &Tiles, position: Vector2<f32>) -> bool {
+    position.x >= 0.0 && position.y >= 0.0 && tiles.is_passable(position.x as u32, position.y as u32)
+}
+
+// Per tile
+const STOCKPILE_CAPACITY: usize = 6;
+
+// Seconds between looking for loose items to haul
+const HAUL_INTERVAL: f32 = 1.0;
+
+// Unless the map says otherwise
+const DEFAULT_ROBOT_CAP: u32 = 10;
+
+// The player needs to build enough of a structure in it to win
+struct WinRegion {
+    min: Vector2<u32>,
+    max: Vector2<u32>, // exclusive
+    class: u32,
+    count: u32,
+}
+
+impl WinRegion {
+    fn is_met(&self, tiles: &Tiles) -> bool {
+        let mut built = 0;
+        for x in self.min.x..self.max.x {
+            for y in self.min.y..self.max.y {
+                let tile = tiles.get(x, y).unwrap();
+                if tile.class() == self.class && !tile.is_under_construction() {
+                    built += 1;
+                }
+            }
+        }
+        built >= self.count
+    }
+
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_vec2_u32(self.min)?;
+        w.write_vec2_u32(self.max)?;
+        w.write_u32(self.class)?;
+        w.write_u32(self.count)
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        Ok(WinRegion {
+            min: r.read_vec2_u32()?,
+            max: r.read_vec2_u32()?,
+            class: r.read_u32()?,
+            count: r.read_u32()?,
+        })
+    }
+}
+
+// Does something when a robot walks into it
+struct Trigger {
+    position: Vector2<f32>,
+    size: Vector2<f32>,
+    message: Option<String>,
+    // Spawned in the middle of it
+    robots: u32,
+    once: bool,
+    fired: bool,
+    occupied: bool, // if a robot was in it last update
+}
+
+impl Trigger {
+    fn contains(&self, pos: Vector2<f32>) -> bool {
+        pos.x >= self.position.x && pos.y >= self.position.y &&
+            pos.x < self.position.x + self.size.x && pos.y < self.position.y + self.size.y
+    }
+
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_vec2_f32(self.position)?;
+        w.write_vec2_f32(self.size)?;
+        w.write_option(self.message.as_ref(), |w, v| w.write_string(v))?;
+        w.write_u32(self.robots)?;
+        w.write_bool(self.once)?;
+        w.write_bool(self.fired)?;
+        w.write_bool(self.occupied)
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        Ok(Trigger {
+            position: r.read_vec2_f32()?,
+            size: r.read_vec2_f32()?,
+            message: r.read_option(|r| r.read_string())?,
+            robots: r.read_u32()?,
+            once: r.read_bool()?,
+            fired: r.read_bool()?,
+            occupied: r.read_bool()?,
+        })
+    }
+}
+
+// Builds robots out of the items brought to the tile above it
+struct Fabricator {
+    tile: Vector2<u32>,
+    // Robots the player still wants from this
+    queued: u32,
+    // Everything a robot takes, to know what was taken in already
+    cost: Recipe,
+    // Still needed for the robot that's being built
+    remaining: Recipe,
+    time_remaining: f32,
+}
+
+impl Fabricator {
+    fn new(tile: Vector2<u32>, fabrication: &Fabrication) -> Self {
+        Fabricator {
+            tile: tile,
+            queued: 0,
+            cost: fabrication.cost.clone(),
+            remaining: fabrication.cost.clone(),
+            time_remaining: fabrication.time,
+        }
+    }
+
+    // Where items are brought to and new robots come out
+    fn drop_off(&self) -> Vector2<u32> {
+        Vector2::new(self.tile.x, self.tile.y + 1)
+    }
+
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_vec2_u32(self.tile)?;
+        w.write_u32(self.queued)?;
+        self.cost.write_save(w)?;
+        self.remaining.write_save(w)?;
+        w.write_f32(self.time_remaining)
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        Ok(Fabricator {
+            tile: r.read_vec2_u32()?,
+            queued: r.read_u32()?,
+            cost: Recipe::read_save(r)?,
+            remaining: Recipe::read_save(r)?,
+            time_remaining: r.read_f32()?,
+        })
+    }
+}
+
 pub struct GameMap {
     tiles: Tiles,
     food_spawners: Vec<FoodSpawner>,
@@ -39,61 +357,129 @@ pub struct GameMap {
     items: Items,
     robots: Robots,
 
-    food_spawn_accum: f32,
+    haul_accum: f32,
     work_queue: WorkQueue,
+
+    fabricators: Vec<Fabricator>,
+    robot_cap: u32,
+
+    win_regions: Vec<WinRegion>,
+    triggers: Vec<Trigger>,
+    camera_start: Option<Vector2<f32>>,
+    won: bool,
 }
 
 impl GameMap {
-    pub fn load(map: Map, rng: &mut StdRng) -> Self {
+    pub fn load(file: MapFile, rng: &mut GameRng) -> Result<Self, MapLoadError> {
+        // How items fall can be tuned per map
+        let defaults = ItemPhysics::default();
+        let physics = ItemPhysics {
+            gravity: positive_map_property(&file, "item_gravity", defaults.gravity)?,
+            terminal_velocity: positive_map_property(&file, "item_terminal_velocity", defaults.terminal_velocity)?,
+        };
+        let robot_cap = map_property(&file, "robot_cap", DEFAULT_ROBOT_CAP)?;
+
+        let map = &file.map;
+
         // Initialize the work queue
         let mut work = WorkQueue::new();
 
-        // Should have 1 layer and 1 object group
-        assert_eq!(map.layers.len(), 1);
-        assert_eq!(map.object_groups.len(), 1);
+        // Should only have the layer we know about
+        if let Some(layer) = map.layers.iter().find(|v| v.name != "Tiles") {
+            return Err(MapLoadError::UnexpectedLayer(layer.name.clone()));
+        }
 
-        // Load in the tiles
-        let tiles = Tiles::load(&map, &mut work);
+        // Load in the tiles, with the kinds of tiles defined in the tileset
+        let defs = TileDefs::load(&file.tileset_tiles)?;
+        let tiles = Tiles::load(map, defs, &mut work)?;
 
-        // Process the food spawners
-        let food_spawners_layer = map.object_groups.iter().find(|v| v.name == "Food Spawners").unwrap();
+        // Set up everything placed in the object groups
         let mut food_spawners = Vec::new();
-        for obj in &food_spawners_layer.objects {
-            if let &Object::Rect { x, y, width, height, visible: _ } = obj {
-                let actual_height = height / 128.0;
-                food_spawners.push(FoodSpawner::new(
-                    [x / 128.0, tiles.height() as f32 - (y / 128.0) - actual_height],
-                    [width / 128.0, actual_height]
-                ));
-            }
-        }
-
-        // Spawn a single robot for the first under construction tile we find
-        // TODO: Temporarily, it's two for the sake of testing
         let mut robots = Robots::new();
-        'spawn_robot: for x in 0..tiles.width() {
-            for y in 0..tiles.height() {
-                let tile = tiles.get(x, y).unwrap();
-                if !tile.is_under_construction() { continue; }
+        let mut win_regions = Vec::new();
+        let mut triggers = Vec::new();
+        let mut camera_start = None;
+        for object in objects::load(&file)? {
+            let blocked = || MapLoadError::BlockedObject {
+                group: object.group.clone(),
+                index: object.index,
+            };
 
-                robots.add(Robot::new(Vector2::new(x as f32 + 0.5, y as f32 + 0.5)));
-                if robots.amount() >= 2 {
-                    break 'spawn_robot;
-                }
+            match object.kind {
+                ObjectKind::FoodSpawn { kind, rate, burst } =>
+                    food_spawners.push(FoodSpawner::new(&object, kind, rate, burst)),
+                ObjectKind::RobotSpawn { count } => {
+                    // Spread the robots out over the width of the area
+                    for i in 0..count {
+                        let x = object.position.x + object.size.x * (i as f32 + 0.5) / count as f32;
+                        let position = Vector2::new(x, object.center().y);
+                        if !passable_at(&tiles, position) {
+                            return Err(blocked());
+                        }
+                        robots.add(Robot::new(position));
+                    }
+                },
+                ObjectKind::CameraStart => camera_start = Some(object.center()),
+                ObjectKind::WinRegion { ref structure, count } => {
+                    let class = tiles.defs().iter().position(|d| d.name == *structure)
+                        .ok_or_else(|| MapLoadError::InvalidObjectProperty {
+                            group: object.group.clone(),
+                            index: object.index,
+                            property: "structure".to_string(),
+                            value: structure.clone(),
+                        })?;
+
+                    // Only the tiles that are inside the map
+                    let clamp = |v: f32, max: u32| if v < 0.0 { 0 } else { ::std::cmp::min(v as u32, max) };
+                    let end = object.position + object.size;
+                    win_regions.push(WinRegion {
+                        min: Vector2::new(clamp(object.position.x, tiles.width()), clamp(object.position.y, tiles.height())),
+                        max: Vector2::new(clamp(end.x.ceil(), tiles.width()), clamp(end.y.ceil(), tiles.height())),
+                        class: class as u32,
+                        count: count,
+                    });
+                },
+                ObjectKind::Trigger { ref message, robots, once } => {
+                    if robots != 0 && !passable_at(&tiles, object.center()) {
+                        return Err(blocked());
+                    }
+
+                    triggers.push(Trigger {
+                        position: object.position,
+                        size: object.size,
+                        message: message.clone(),
+                        robots: robots,
+                        once: once,
+                        fired: false,
+                        occupied: false,
+                    });
+                },
             }
         }
+        if !food_spawners.iter().any(|s| s.kind == ItemKind::Food) {
+            return Err(MapLoadError::NoFoodSpawners);
+        }
 
         // Create the actual struct
         let mut map = GameMap {
             tiles: tiles,
             food_spawners: food_spawners,
 
-            items: Items::new(),
+            items: Items::new(physics),
             robots: robots,
 
-            food_spawn_accum: 0.0,
+            haul_accum: 0.0,
             work_queue: work,
+
+            fabricators: Vec::new(),
+            robot_cap: robot_cap,
+
+            win_regions: win_regions,
+            triggers: triggers,
+            camera_start: camera_start,
+            won: false,
         };
+        map.find_fabricators();
 
         // Spawn some food and advance time before the first frame
         for _ in 0..6 {
@@ -105,10 +491,99 @@ impl GameMap {
                 map.items.update(&map.tiles, 0.2);
             }
 
-            map.spawn_food(rng);
+            map.spawn_food(4.0, rng);
         }
 
-        map
+        Ok(map)
+    }
+
+    pub fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        self.tiles.write_save(w)?;
+        w.write_len(self.food_spawners.len())?;
+        for spawner in &self.food_spawners {
+            spawner.write_save(w)?;
+        }
+        self.items.write_save(w)?;
+        self.robots.write_save(w)?;
+        w.write_f32(self.haul_accum)?;
+        self.work_queue.write_save(w)?;
+        w.write_len(self.fabricators.len())?;
+        for fabricator in &self.fabricators {
+            fabricator.write_save(w)?;
+        }
+        w.write_u32(self.robot_cap)?;
+        w.write_len(self.win_regions.len())?;
+        for region in &self.win_regions {
+            region.write_save(w)?;
+        }
+        w.write_len(self.triggers.len())?;
+        for trigger in &self.triggers {
+            trigger.write_save(w)?;
+        }
+        w.write_option(self.camera_start, |w, v| w.write_vec2_f32(v))?;
+        w.write_bool(self.won)
+    }
+
+    pub fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let tiles = Tiles::read_save(r)?;
+        let mut food_spawners = Vec::new();
+        for _ in 0..r.read_len()? {
+            food_spawners.push(FoodSpawner::read_save(r)?);
+        }
+        let items = Items::read_save(r)?;
+        let robots = Robots::read_save(r)?;
+        let haul_accum = r.read_f32()?;
+        let work_queue = WorkQueue::read_save(r)?;
+        let mut fabricators = Vec::new();
+        for _ in 0..r.read_len()? {
+            fabricators.push(Fabricator::read_save(r)?);
+        }
+        let robot_cap = r.read_u32()?;
+        let mut win_regions = Vec::new();
+        for _ in 0..r.read_len()? {
+            win_regions.push(WinRegion::read_save(r)?);
+        }
+        let mut triggers = Vec::new();
+        for _ in 0..r.read_len()? {
+            triggers.push(Trigger::read_save(r)?);
+        }
+        let camera_start = r.read_option(|r| r.read_vec2_f32())?;
+        let won = r.read_bool()?;
+
+        // Everything that points at tiles needs to stay on the map, or it'd panic later on
+        robots.validate(&tiles)?;
+        work_queue.validate(&tiles)?;
+        for fabricator in &fabricators {
+            tiles.check_saved_pos(fabricator.tile)?;
+            let class = tiles.get(fabricator.tile.x, fabricator.tile.y).unwrap().class();
+            if tiles.defs().get(class).fabricator.is_none() {
+                return Err(SaveError::Corrupt("fabricator is on a tile that isn't one".to_string()));
+            }
+        }
+        for region in &win_regions {
+            if region.max.x > tiles.width() || region.max.y > tiles.height() {
+                return Err(SaveError::Corrupt("win region is outside of the map".to_string()));
+            }
+        }
+
+        Ok(GameMap {
+            tiles: tiles,
+            food_spawners: food_spawners,
+
+            items: items,
+            robots: robots,
+
+            haul_accum: haul_accum,
+            work_queue: work_queue,
+
+            fabricators: fabricators,
+            robot_cap: robot_cap,
+
+            win_regions: win_regions,
+            triggers: triggers,
+            camera_start: camera_start,
+            won: won,
+        })
     }
 
     pub fn tiles(&self) -> &Tiles {
@@ -123,44 +598,669 @@ impl GameMap {
         &self.robots
     }
 
-    pub fn start_construction(&mut self, pos: Vector2<u32>, class: u32) {
-        let tile = self.tiles.get_mut(pos.x, pos.y).unwrap();
+    pub fn camera_start(&self) -> Option<Vector2<f32>> {
+        self.camera_start
+    }
 
-        // Can't overwrite an existing construction
-        if tile.is_under_construction() {
-            return;
+    // Maps without win regions can't be won
+    pub fn is_won(&self) -> bool {
+        self.won
+    }
+
+    pub fn can_build(&self, pos: Vector2<u32>, class: u32) -> bool {
+        let defs = self.tiles.defs();
+        let tile = if let Some(tile) = self.tiles.get(pos.x, pos.y) {
+            tile
+        } else {
+            return false;
+        };
+
+        // Only buildable structures can be placed, and not on top of existing constructions or
+        // other structures
+        defs.get(class).buildable &&
+            !tile.is_under_construction() &&
+            !defs.get(tile.class()).structure
+    }
+
+    // False if it can't be built there
+    pub fn start_construction(&mut self, pos: Vector2<u32>, class: u32) -> bool {
+        if !self.can_build(pos, class) {
+            return false;
         }
 
         // Set the tile to under construction
-        tile.set_construction(class);
+        let def = self.tiles.defs().get(class).clone();
+        self.tiles.get_mut(pos.x, pos.y).unwrap().set_construction(class, &def);
 
         // Create a work item for that tile
-        self.work_queue.publish(WorkEntry::new(pos));
+        self.work_queue.publish(WorkEntry::new(WorkKind::Build, pos).with_max_workers(def.max_builders()));
+        true
+    }
+
+    // False if there's nothing to take apart, what it was built with is given back
+    pub fn start_deconstruction(&mut self, pos: Vector2<u32>) -> bool {
+        let def = {
+            let tile = if let Some(tile) = self.tiles.get(pos.x, pos.y) {
+                tile
+            } else {
+                return false;
+            };
+
+            let def = self.tiles.defs().get(tile.class());
+            if !def.structure || tile.is_under_construction() || tile.is_marked_for_deconstruction() {
+                return false;
+            }
+            def.clone()
+        };
+
+        self.tiles.get_mut(pos.x, pos.y).unwrap().set_deconstruction(&def);
+        self.work_queue.publish(WorkEntry::new(WorkKind::Deconstruct, pos));
+        true
+    }
+
+    // False if nothing was ordered, resources already delivered are dropped
+    pub fn cancel_order(&mut self, pos: Vector2<u32>) -> bool {
+        let work_id = if let Some(id) = self.work_queue.find(pos) {
+            id
+        } else {
+            return false;
+        };
+
+        // Withdraw the work and stop whoever was doing it
+        for robot in self.work_queue.cancel(work_id) {
+            self.robots.cancel_work(robot, &mut self.items);
+        }
+
+        // Clear the order from the tile itself
+        let class = self.tiles.get(pos.x, pos.y).unwrap().under_construction_class();
+        let remaining = self.tiles.get_mut(pos.x, pos.y).unwrap().cancel_orders();
+
+        // Give back what was already delivered
+        if let (Some(class), Some(remaining)) = (class, remaining) {
+            let cost = self.tiles.defs().get(class).cost.clone();
+            let position = pos.cast::<f32>() + Vector2::new(0.5, 0.5);
+            for kind in ItemKind::all() {
+                for _ in remaining.get(*kind)..cost.get(*kind) {
+                    self.items.add(Item::new(*kind, position));
+                }
+            }
+        }
+
+        true
+    }
+
+    // The new priority, if there's an order on the tile
+    pub fn bump_priority(&mut self, pos: Vector2<u32>) -> Option<u32> {
+        self.work_queue.find(pos).and_then(|id| self.work_queue.bump_priority(id))
+    }
+
+    // If it's a stockpile now, None if the tile can't hold items, they need a solid tile below
+    pub fn toggle_stockpile(&mut self, pos: Vector2<u32>) -> Option<bool> {
+        let on_floor = pos.y > 0 && !self.tiles.is_passable(pos.x, pos.y - 1);
+        if !self.tiles.is_passable(pos.x, pos.y) || !on_floor {
+            return None;
+        }
+
+        let tile = self.tiles.get_mut(pos.x, pos.y).unwrap();
+        let stockpile = !tile.is_stockpile();
+        tile.set_stockpile(stockpile);
+        Some(stockpile)
+    }
+
+    // Returns how many it has queued now
+    pub fn queue_robot(&mut self, pos: Vector2<u32>) -> Option<u32> {
+        self.fabricators.iter_mut().find(|f| f.tile == pos).map(|f| {
+            f.queued += 1;
+            f.queued
+        })
+    }
+
+    pub fn robot_cap(&self) -> u32 {
+        self.robot_cap
+    }
+
+    pub fn set_robot_cap(&mut self, robot_cap: u32) {
+        self.robot_cap = robot_cap;
     }
 
     pub fn get_tile(&self, pos: Vector2<u32>) -> Option<u32> {
         self.tiles.get(pos.x, pos.y).map(|v| v.class())
     }
 
-    pub fn update(&mut self, delta: f32, rng: &mut StdRng) {
+    // Human readable snapshot of the state, for comparing runs
+    pub fn dump<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        // Tiles, top row first so it looks like the map in the editor
+        writeln!(out, "Tiles:")?;
+        for y in (0..self.tiles.height()).rev() {
+            let mut line = String::new();
+            for x in 0..self.tiles.width() {
+                let tile = self.tiles.get(x, y).unwrap();
+                let def = self.tiles.defs().get(tile.class());
+                line.push(if tile.is_under_construction() {
+                    '+'
+                } else if tile.is_marked_for_deconstruction() {
+                    '-'
+                } else if self.tiles.is_stockpile(x, y) {
+                    's'
+                } else if tile.class() == self.tiles.defs().empty() {
+                    '.'
+                } else if def.structure {
+                    def.name.chars().next().map(|c| c.to_ascii_uppercase()).unwrap_or('?')
+                } else if def.solid {
+                    '#'
+                } else {
+                    '?'
+                });
+            }
+            writeln!(out, "{}", line)?;
+        }
+
+        // Robots
+        writeln!(out, "Robots:")?;
+        for (id, robot) in self.robots.iter() {
+            let pos = robot.position();
+            writeln!(out, "  {} at {:.3},{:.3} {:?} carrying {:?} energy {:.3} wear {:.3}",
+                id, pos.x, pos.y, robot.state(), robot.inventory(), robot.energy(), robot.wear())?;
+        }
+
+        // Items
+        writeln!(out, "Items:")?;
+        for (id, item) in self.items.iter() {
+            writeln!(out, "  {} {} at {:.3},{:.3} {:?} {:?} lifetime {:.3}{}",
+                id, item.kind.name(), item.position.x, item.position.y, item.state, item.freshness(),
+                item.lifetime,
+                if item.claimed { " claimed" } else { "" })?;
+        }
+
+        // Fabricators
+        writeln!(out, "Fabricators (cap {}):", self.robot_cap)?;
+        for fabricator in &self.fabricators {
+            writeln!(out, "  {},{} queued {} needs {:?} time {:.3}",
+                fabricator.tile.x, fabricator.tile.y, fabricator.queued, fabricator.remaining,
+                fabricator.time_remaining)?;
+        }
+
+        writeln!(out, "Won: {}", self.won)?;
+
+        Ok(())
+    }
+
+    pub fn update(&mut self, delta: f32, rng: &mut GameRng) {
         // Update all items
         self.items.update(&self.tiles, delta);
+        self.publish_debris_clearing();
 
-        // Spawn a food blob if enough time has passed
-        self.food_spawn_accum += delta;
-        while self.food_spawn_accum > 4.0 {
-            self.food_spawn_accum -= 4.0;
-            self.spawn_food(rng);
+        // Every now and then, have robots bring loose items to stockpiles
+        self.haul_accum += delta;
+        while self.haul_accum > HAUL_INTERVAL {
+            self.haul_accum -= HAUL_INTERVAL;
+            self.publish_hauling();
+            self.publish_fabricator_supply();
+            self.publish_repairs();
         }
 
+        // Spawn food from every spawner that's due
+        self.spawn_food(delta, rng);
+
         // Update all the robots
         self.robots.update(delta, &mut self.items, &mut self.tiles, &mut self.work_queue, rng);
+
+        if self.work_queue.take_structures_changed() {
+            self.find_fabricators();
+            self.robots.forget_distances();
+        }
+        self.update_fabricators(delta);
+        self.update_triggers();
+
+        // Check if the player has done everything the map asks for
+        if !self.won && !self.win_regions.is_empty() &&
+            self.win_regions.iter().all(|r| r.is_met(&self.tiles)) {
+            self.won = true;
+            println!("Every win region is complete, the map has been won");
+        }
+    }
+
+    fn update_triggers(&mut self) {
+        let positions: Vec<_> = self.robots.iter().map(|(_, r)| r.position()).collect();
+
+        for trigger in &mut self.triggers {
+            let occupied = positions.iter().any(|p| trigger.contains(*p));
+            let entered = occupied && !trigger.occupied;
+            trigger.occupied = occupied;
+            if !entered || (trigger.once && trigger.fired) {
+                continue;
+            }
+
+            // Something may have been built over it since the map was made, try again next time
+            let center = trigger.position + trigger.size * 0.5;
+            if trigger.robots != 0 && !passable_at(&self.tiles, center) {
+                println!("A trigger couldn't place its robots, its center is blocked");
+                continue;
+            }
+
+            trigger.fired = true;
+            if let Some(ref message) = trigger.message {
+                println!("{}", message);
+            }
+            for _ in 0..trigger.robots {
+                self.robots.add(Robot::new(center));
+            }
+        }
     }
 
-    fn spawn_food(&mut self, rng: &mut StdRng) {
-        assert_eq!(self.food_spawners.len(), 1);
-        let spawner = &self.food_spawners[0];
-        let item = spawner.spawn(rng);
-        self.items.add(item);
+    // As they get built and removed
+    fn find_fabricators(&mut self) {
+        let mut found = Vec::new();
+        let defs = self.tiles.defs();
+        self.tiles.for_each(|x, y, tile| {
+            if tile.is_under_construction() {
+                return;
+            }
+            if let Some(ref fabrication) = defs.get(tile.class()).fabricator {
+                found.push((Vector2::new(x, y), fabrication.clone()));
+            }
+        });
+
+        // Keep the progress of the ones that are still there
+        let mut fabricators = Vec::new();
+        for (tile, fabrication) in found {
+            let existing = self.fabricators.iter().position(|f| f.tile == tile);
+            fabricators.push(match existing {
+                Some(i) => self.fabricators.swap_remove(i),
+                None => Fabricator::new(tile, &fabrication),
+            });
+        }
+
+        // The ones that are left were taken apart, drop what they already took in
+        for removed in ::std::mem::replace(&mut self.fabricators, fabricators) {
+            let drop_off = removed.drop_off();
+            let drop_off = if self.tiles.is_passable(drop_off.x, drop_off.y) { drop_off } else { removed.tile };
+            let position = drop_off.cast::<f32>() + Vector2::new(0.5, 0.5);
+            for kind in ItemKind::all() {
+                for _ in removed.remaining.get(*kind)..removed.cost.get(*kind) {
+                    self.items.add(Item::new(*kind, position));
+                }
+            }
+        }
+    }
+
+    // Takes in the items brought to fabricators, and builds robots once they have everything
+    fn update_fabricators(&mut self, delta: f32) {
+        let mut robot_count = self.robots.amount() as u32;
+        for fabricator in &mut self.fabricators {
+            let drop_off = fabricator.drop_off();
+            if !self.tiles.is_passable(drop_off.x, drop_off.y) {
+                continue;
+            }
+            let min = drop_off.cast::<f32>();
+            let center = min + Vector2::new(0.5, 0.5);
+            let class = self.tiles.get(fabricator.tile.x, fabricator.tile.y).unwrap().class();
+            let fabrication = self.tiles.defs().get(class).fabricator.as_ref().unwrap();
+
+            // Repair the wrecks that were brought here, or take them apart if there's no room
+            for id in self.items.in_area(min, min + Vector2::new(1.0, 1.0)) {
+                {
+                    let item = self.items.get(id).unwrap();
+                    if item.kind != ItemKind::Wreck || item.claimed || item.state == ItemState::Carried {
+                        continue;
+                    }
+                }
+                self.items.remove(id);
+
+                if robot_count < self.robot_cap {
+                    let robot = self.robots.add(Robot::new(center));
+                    robot_count += 1;
+                    println!("Fabricator at {:?} repaired a wreck into {}", fabricator.tile, robot);
+                } else {
+                    for _ in 0..fabrication.cost.get(ItemKind::Mineral) {
+                        self.items.add(Item::new(ItemKind::Mineral, center));
+                    }
+                    println!("Fabricator at {:?} recycled a wreck", fabricator.tile);
+                }
+            }
+
+            if fabricator.queued == 0 || robot_count >= self.robot_cap {
+                continue;
+            }
+
+            // Take in what's lying on top of it, if it's still needed
+            for id in self.items.in_area(min, min + Vector2::new(1.0, 1.0)) {
+                let kind = {
+                    let item = self.items.get(id).unwrap();
+                    if item.claimed || item.state == ItemState::Carried || item.freshness() == Freshness::Rotten {
+                        continue;
+                    }
+                    item.kind
+                };
+                if fabricator.remaining.take(kind) {
+                    self.items.remove(id);
+                }
+            }
+            if !fabricator.remaining.is_empty() {
+                continue;
+            }
+
+            fabricator.time_remaining -= delta;
+            if fabricator.time_remaining > 0.0 {
+                continue;
+            }
+
+            // The robot's done, start on the next one
+            let id = self.robots.add(Robot::new(center));
+            println!("Fabricator at {:?} built {}", fabricator.tile, id);
+            robot_count += 1;
+            fabricator.queued -= 1;
+
+            fabricator.remaining = fabrication.cost.clone();
+            fabricator.time_remaining = fabrication.time;
+        }
+    }
+
+    // Only what isn't already on the way
+    fn publish_fabricator_supply(&mut self) {
+        let robot_count = self.robots.amount() as u32;
+        for fabricator in &self.fabricators {
+            if fabricator.queued == 0 || robot_count >= self.robot_cap {
+                continue;
+            }
+            let drop_off = fabricator.drop_off();
+            if !self.tiles.is_passable(drop_off.x, drop_off.y) {
+                continue;
+            }
+
+            // Leave out what's already there or being brought
+            let mut missing = fabricator.remaining.clone();
+            let min = drop_off.cast::<f32>();
+            let center = min + Vector2::new(0.5, 0.5);
+            for id in self.items.in_area(min, min + Vector2::new(1.0, 1.0)) {
+                missing.take(self.items.get(id).unwrap().kind);
+            }
+            for id in self.work_queue.hauled_to(drop_off) {
+                if let Some(item) = self.items.get(id) {
+                    missing.take(item.kind);
+                }
+            }
+            if missing.is_empty() {
+                continue;
+            }
+
+            let distances = if let Some(distances) = pathfinding::distances_from(&self.tiles, center) {
+                distances
+            } else {
+                continue;
+            };
+
+            while let Some(kind) = missing.next_needed() {
+                missing.take(kind);
+
+                // The closest usable item of this kind, stockpiled or not
+                let mut closest: Option<(Handle, f32)> = None;
+                for (id, item) in self.items.iter() {
+                    if item.kind != kind || item.state != ItemState::Static || item.claimed ||
+                        item.freshness() == Freshness::Rotten || self.work_queue.is_hauling(id) {
+                        continue;
+                    }
+                    if let Some(distance) = distances.get(item.position.cast()) {
+                        if closest.map(|(_, d)| distance < d).unwrap_or(true) {
+                            closest = Some((id, distance));
+                        }
+                    }
+                }
+
+                if let Some((id, _)) = closest {
+                    self.work_queue.publish(WorkEntry::new(WorkKind::Haul(id), drop_off).with_priority(1));
+                }
+            }
+        }
+    }
+
+    // To the closest fabricator they can be carried to
+    fn publish_repairs(&mut self) {
+        let drop_offs: Vec<_> = self.fabricators.iter().map(|f| f.drop_off()).collect();
+        let work_queue = &self.work_queue;
+        let wrecks: Vec<_> = self.items.iter()
+            .filter(|&(id, item)| item.kind == ItemKind::Wreck && item.state == ItemState::Static &&
+                !item.claimed && !work_queue.is_hauling(id) && !drop_offs.contains(&item.position.cast()))
+            .map(|(id, item)| (id, item.position.cast::<u32>()))
+            .collect();
+        if wrecks.is_empty() {
+            return;
+        }
+
+        // How far every fabricator is from everywhere
+        let mut fabricators = Vec::new();
+        for drop_off in drop_offs {
+            if !self.tiles.is_passable(drop_off.x, drop_off.y) {
+                continue;
+            }
+            let center = drop_off.cast::<f32>() + Vector2::new(0.5, 0.5);
+            if let Some(distances) = pathfinding::distances_from(&self.tiles, center) {
+                fabricators.push((drop_off, distances));
+            }
+        }
+
+        for (id, tile) in wrecks {
+            let mut closest: Option<(Vector2<u32>, f32)> = None;
+            for &(drop_off, ref distances) in &fabricators {
+                if let Some(distance) = distances.get(tile) {
+                    if closest.map(|(_, d)| distance < d).unwrap_or(true) {
+                        closest = Some((drop_off, distance));
+                    }
+                }
+            }
+
+            if let Some((drop_off, _)) = closest {
+                self.work_queue.publish(WorkEntry::new(WorkKind::Repair(id), drop_off));
+            }
+        }
+    }
+
+    // For residue that's come to rest, if nobody's clearing it already
+    fn publish_debris_clearing(&mut self) {
+        let residue: Vec<_> = self.items.iter()
+            .filter(|&(_, item)| item.kind == ItemKind::Residue && item.state == ItemState::Static && !item.claimed)
+            .map(|(id, item)| (id, item.position))
+            .collect();
+
+        for (id, position) in residue {
+            self.items.get_mut(id).unwrap().claimed = true;
+            self.work_queue.publish(WorkEntry::new(WorkKind::ClearDebris(id), position.cast()));
+        }
+    }
+
+    // To the closest stockpile with room left
+    fn publish_hauling(&mut self) {
+        // Find out how much room every stockpile has left, and how far it is from everywhere
+        let mut stockpiles = Vec::new();
+        for x in 0..self.tiles.width() {
+            for y in 0..self.tiles.height() {
+                if !self.tiles.is_stockpile(x, y) {
+                    continue;
+                }
+
+                let pos = Vector2::new(x, y);
+                let min = pos.cast::<f32>();
+                let stored = self.items.in_area(min, min + Vector2::new(1.0, 1.0)).len() +
+                    self.work_queue.hauled_to(pos).len();
+                if stored >= STOCKPILE_CAPACITY {
+                    continue;
+                }
+
+                if let Some(distances) = pathfinding::distances_from(&self.tiles, min + Vector2::new(0.5, 0.5)) {
+                    stockpiles.push((pos, STOCKPILE_CAPACITY - stored, distances));
+                }
+            }
+        }
+        if stockpiles.is_empty() {
+            return;
+        }
+
+        // Usable items that are lying around outside of stockpiles, and weren't brought to a fabricator
+        let tiles = &self.tiles;
+        let work_queue = &self.work_queue;
+        let drop_offs: Vec<_> = self.fabricators.iter().map(|f| f.drop_off()).collect();
+        let loose: Vec<_> = self.items.iter()
+            .filter(|&(id, item)| item.state == ItemState::Static && !item.claimed &&
+                item.kind != ItemKind::Residue && item.kind != ItemKind::Wreck &&
+                item.freshness() != Freshness::Rotten && !items::in_stockpile(tiles, item.position) && !work_queue.is_hauling(id) &&
+                !drop_offs.contains(&item.position.cast()))
+            .map(|(id, item)| (id, item.position.cast::<u32>()))
+            .collect();
+
+        for (id, tile) in loose {
+            // The closest stockpile that can be reached from the item
+            let mut closest: Option<(usize, f32)> = None;
+            for (i, &(_, room, ref distances)) in stockpiles.iter().enumerate() {
+                if room == 0 {
+                    continue;
+                }
+                if let Some(distance) = distances.get(tile) {
+                    if closest.map(|(_, d)| distance < d).unwrap_or(true) {
+                        closest = Some((i, distance));
+                    }
+                }
+            }
+
+            if let Some((i, _)) = closest {
+                stockpiles[i].1 -= 1;
+                self.work_queue.publish(WorkEntry::new(WorkKind::Haul(id), stockpiles[i].0));
+            }
+        }
+    }
+
+    fn spawn_food(&mut self, delta: f32, rng: &mut GameRng) {
+        for spawner in &mut self.food_spawners {
+            spawner.update(delta, &mut self.items, rng);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use cgmath::Vector2;
+    use items::{Item, ItemKind, ItemState, Items, ITEM_SIZE};
+    use rng::GameRng;
+    use robots::{RobotState, Robots, WorkQueue};
+    use tiles::Tiles;
+    use tmx::MapFile;
+    use super::{GameMap, STOCKPILE_CAPACITY};
+
+    // The shipped map with everything on it taken away, and the tiles swapped out
+    fn bare_map(rows: &[&str]) -> GameMap {
+        let file = MapFile::load(Path::new("assets/map.tmx")).unwrap();
+        let mut map = GameMap::load(file, &mut GameRng::from_seed(1)).unwrap();
+        map.tiles = Tiles::from_rows(rows);
+        map.food_spawners.clear();
+        map.items = Items::new(map.items.physics());
+        map.robots = Robots::new();
+        map.work_queue = WorkQueue::new();
+        map.fabricators.clear();
+        map.win_regions.clear();
+        map.triggers.clear();
+        map
+    }
+
+    #[test]
+    fn stockpiles_need_a_floor() {
+        let mut map = bare_map(&[
+            "....",
+            "...#",
+            "####",
+        ]);
+
+        assert_eq!(map.toggle_stockpile(Vector2::new(1, 1)), Some(true));
+        assert!(map.tiles.is_stockpile(1, 1));
+        assert_eq!(map.toggle_stockpile(Vector2::new(1, 1)), Some(false));
+        assert!(!map.tiles.is_stockpile(1, 1));
+
+        // Inside a wall and hanging in the air don't work, on top of a wall does
+        assert_eq!(map.toggle_stockpile(Vector2::new(3, 1)), None);
+        assert_eq!(map.toggle_stockpile(Vector2::new(1, 2)), None);
+        assert_eq!(map.toggle_stockpile(Vector2::new(3, 2)), Some(true));
+    }
+
+    #[test]
+    fn loose_items_are_hauled_to_the_closest_stockpile_with_room() {
+        let mut map = bare_map(&[
+            "........",
+            "........",
+            "########",
+        ]);
+        assert_eq!(map.toggle_stockpile(Vector2::new(0, 1)), Some(true));
+        assert_eq!(map.toggle_stockpile(Vector2::new(7, 1)), Some(true));
+
+        // More than fit in the closer stockpile, next to it on the floor
+        let loose: Vec<_> = (0..STOCKPILE_CAPACITY + 2)
+            .map(|i| {
+                let position = Vector2::new(4.6 + i as f32 * 0.3, 1.0 + ITEM_SIZE / 2.0);
+                map.items.add(Item::new(ItemKind::Mineral, position))
+            })
+            .collect();
+
+        // Ones that are already stockpiled and residue stay where they are
+        let stored = map.items.add(Item::new(ItemKind::Mineral, Vector2::new(0.5, 1.5)));
+        let residue = map.items.add(Item::new(ItemKind::Residue, Vector2::new(3.5, 1.5)));
+        for _ in 0..20 {
+            map.items.update(&map.tiles, 0.1);
+        }
+
+        map.publish_hauling();
+        let near = map.work_queue.hauled_to(Vector2::new(7, 1));
+        let far = map.work_queue.hauled_to(Vector2::new(0, 1));
+        assert_eq!(near.len(), STOCKPILE_CAPACITY);
+        assert_eq!(far.len(), 2);
+        assert!(loose.iter().all(|id| near.contains(id) || far.contains(id)));
+        assert!(!map.work_queue.is_hauling(stored));
+        assert!(!map.work_queue.is_hauling(residue));
+
+        // Items that are already being hauled aren't hauled twice
+        map.publish_hauling();
+        assert_eq!(map.work_queue.hauled_to(Vector2::new(7, 1)).len(), STOCKPILE_CAPACITY);
+        assert_eq!(map.work_queue.hauled_to(Vector2::new(0, 1)).len(), 2);
+    }
+
+    #[test]
+    fn cancelling_lets_go_of_carried_items() {
+        let file = MapFile::load(Path::new("assets/map.tmx")).unwrap();
+        let mut map = GameMap::load(file, &mut GameRng::from_seed(1)).unwrap();
+        map.robots = Robots::new();
+        map.items = Items::new(map.items.physics());
+        map.work_queue = WorkQueue::new();
+
+        // Build something on open ground, with a robot on the way bringing the first item
+        let class = map.tiles.defs().buildable()[0];
+        let spot = (1..map.tiles.height()).flat_map(|y| (0..map.tiles.width()).map(move |x| Vector2::new(x, y)))
+            .find(|p| {
+                map.can_build(*p, class) && map.tiles.is_passable(p.x, p.y) && !map.tiles.is_passable(p.x, p.y - 1)
+            })
+            .unwrap();
+        assert!(map.start_construction(spot, class));
+        let entry = map.work_queue.find(spot).unwrap();
+
+        let kind = map.tiles.get(spot.x, spot.y).unwrap().unpromised_resource().unwrap();
+        map.tiles.get_mut(spot.x, spot.y).unwrap().promise_resource(kind);
+        let item = map.items.add(Item::new(kind, spot.cast() + Vector2::new(0.5, 0.5)));
+        map.items.get_mut(item).unwrap().state = ItemState::Carried;
+        map.items.get_mut(item).unwrap().claimed = true;
+        let robot = map.robots.add_carrying(&mut map.work_queue, entry, item, kind);
+
+        assert!(map.cancel_order(spot));
+
+        assert!(map.work_queue.get(entry).is_none());
+        assert!(!map.tiles.get(spot.x, spot.y).unwrap().is_under_construction());
+        let (_, robot) = map.robots.iter().find(|&(id, _)| id == robot).unwrap();
+        match *robot.state() {
+            RobotState::Waiting => (),
+            ref state => panic!("robot is still {:?}", state),
+        }
+        assert_eq!(robot.inventory(), None);
+
+        // Once it's landed someone else can have it
+        for _ in 0..60 {
+            map.items.update(&map.tiles, 0.1);
+        }
+        assert!(!map.items.get(item).unwrap().claimed);
+        assert_eq!(map.items.claim_resource(spot.cast(), kind, &map.tiles, &[]), Some(item));
     }
 }
@@ -1,16 +1,37 @@
-use tiled::Map;
-use rand::StdRng;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use rng::GameRng;
 use cgmath::{Vector2, EuclideanVector};
-use map::GameMap;
+use map::{GameMap, MapLoadError};
+use tmx::MapFile;
+use replay::{Recording, InputEvent, InputEventKind};
+use save::{SaveWriter, SaveReader, SaveError};
+
+// Fixed step length in seconds, whatever the frame rate
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+
+// Further behind than this many steps, the time is dropped instead
+const MAX_STEPS_PER_UPDATE: u32 = 10;
+
+// When the map doesn't say
+const DEFAULT_CAMERA_START: Vector2<f32> = Vector2 { x: 25.0, y: 32.0 };
 
 enum_from_primitive! {
-    #[derive(Debug, PartialEq)]
+    #[derive(Debug, PartialEq, Clone, Copy)]
     pub enum GameButton {
         CameraUp,
         CameraLeft,
         CameraDown,
         CameraRight,
         Interact,
+        NextStructure,
+        PreviousStructure,
+        Deconstruct,
+        BumpPriority,
+        Stockpile,
+        QueueRobot,
+        RaiseRobotCap,
+        LowerRobotCap,
     }
 }
 
@@ -22,7 +43,7 @@ pub struct InputState {
 impl InputState {
     fn new() -> Self {
         InputState {
-            keys: vec![false; GameButton::Interact as usize + 1],
+            keys: vec![false; GameButton::LowerRobotCap as usize + 1],
             hover_tile: Vector2::new(0, 0),
         }
     }
@@ -76,9 +97,9 @@ pub struct GameCamera {
 }
 
 impl GameCamera {
-    fn new() -> Self {
+    fn new(position: Vector2<f32>) -> Self {
         GameCamera {
-            position: Vector2::new(25.0, 32.0)
+            position: position
         }
     }
 
@@ -95,31 +116,109 @@ impl GameCamera {
 }
 
 struct BuildingBehavior {
-    prev_button: bool
+    // Buttons as they were last update, to tell presses and releases apart
+    prev_keys: Vec<bool>,
+    // Index into the buildable classes
+    selected: usize,
 }
 
 impl BuildingBehavior {
     fn new() -> Self {
         BuildingBehavior {
-            prev_button: false
+            prev_keys: InputState::new().keys,
+            selected: 0,
+        }
+    }
+
+    fn selected_class(&self, map: &GameMap) -> Option<u32> {
+        let buildable = map.tiles().defs().buildable();
+        if buildable.is_empty() {
+            None
+        } else {
+            Some(buildable[self.selected % buildable.len()])
         }
     }
 
+    fn pressed(&self, input: &InputState, button: GameButton) -> bool {
+        input.get(button) && !self.prev_keys[button as usize]
+    }
+
+    fn released(&self, input: &InputState, button: GameButton) -> bool {
+        !input.get(button) && self.prev_keys[button as usize]
+    }
+
     fn update(&mut self, input: &InputState, map: &mut GameMap) {
-        let current_button = input.get(GameButton::Interact);
+        // Cycle through the palette when the buttons get pressed
+        let buildable_amount = map.tiles().defs().buildable().len();
+        if buildable_amount != 0 {
+            if self.pressed(input, GameButton::NextStructure) {
+                self.selected = (self.selected + 1) % buildable_amount;
+            }
+            if self.pressed(input, GameButton::PreviousStructure) {
+                self.selected = (self.selected + buildable_amount - 1) % buildable_amount;
+            }
+        }
 
-        if current_button != self.prev_button && !current_button {
+        if self.released(input, GameButton::Interact) {
             let tile_pos = input.get_hover_tile();
 
-            // If the tile's already a 2 or a 3, don't do anything
-            let current_class = map.get_tile(tile_pos).unwrap();
-            if current_class != 2 && current_class != 3 {
-                // Create a construction where the mouse is
-                map.start_construction(tile_pos, 2);
+            // Create a construction where the mouse is, if that structure can go there
+            if let Some(class) = self.selected_class(map) {
+                if !map.start_construction(tile_pos, class) {
+                    println!("Can't build {} at {:?}", map.tiles().defs().get(class).name, tile_pos);
+                }
+            }
+        }
+
+        // Cancel whatever's ordered under the mouse, or if nothing is order it to be taken apart
+        if self.released(input, GameButton::Deconstruct) {
+            let tile_pos = input.get_hover_tile();
+            if !map.cancel_order(tile_pos) && !map.start_deconstruction(tile_pos) {
+                println!("Nothing to deconstruct at {:?}", tile_pos);
+            }
+        }
+
+        // Make the order under the mouse more important
+        if self.pressed(input, GameButton::BumpPriority) {
+            let tile_pos = input.get_hover_tile();
+            match map.bump_priority(tile_pos) {
+                Some(priority) => println!("Order at {:?} now has priority {}", tile_pos, priority),
+                None => println!("Nothing ordered at {:?}", tile_pos),
             }
         }
 
-        self.prev_button = current_button;
+        // Mark the tile under the mouse as a stockpile, or unmark it
+        if self.pressed(input, GameButton::Stockpile) {
+            let tile_pos = input.get_hover_tile();
+            match map.toggle_stockpile(tile_pos) {
+                Some(true) => println!("Tile {:?} is now a stockpile", tile_pos),
+                Some(false) => println!("Tile {:?} is no longer a stockpile", tile_pos),
+                None => println!("Can't put a stockpile at {:?}", tile_pos),
+            }
+        }
+
+        // Order another robot from the fabricator under the mouse
+        if self.pressed(input, GameButton::QueueRobot) {
+            let tile_pos = input.get_hover_tile();
+            match map.queue_robot(tile_pos) {
+                Some(queued) => println!("Fabricator at {:?} has {} robots queued", tile_pos, queued),
+                None => println!("No fabricator at {:?}", tile_pos),
+            }
+        }
+
+        // Change how many robots fabricators will build up to
+        if self.pressed(input, GameButton::RaiseRobotCap) {
+            let cap = map.robot_cap() + 1;
+            map.set_robot_cap(cap);
+            println!("Robot cap is now {}", cap);
+        }
+        if self.pressed(input, GameButton::LowerRobotCap) && map.robot_cap() > 0 {
+            let cap = map.robot_cap() - 1;
+            map.set_robot_cap(cap);
+            println!("Robot cap is now {}", cap);
+        }
+
+        self.prev_keys.clone_from(&input.keys);
     }
 }
 
@@ -130,23 +229,69 @@ pub struct GameModel {
 
     input: InputState,
     building: BuildingBehavior,
-    rng: StdRng,
+    rng: GameRng,
+    time_accum: f32,
+    steps: u64,
+    recording: Option<Recording>,
 }
 
 impl GameModel {
-    pub fn new(map: Map) -> Self {
-        let mut rng = StdRng::new().unwrap();
-        let game_map = GameMap::load(map, &mut rng);
+    pub fn new(file: MapFile, seed: u64) -> Result<Self, MapLoadError> {
+        let mut rng = GameRng::from_seed(seed);
+        let game_map = GameMap::load(file, &mut rng)?;
+        let camera_start = game_map.camera_start().unwrap_or(DEFAULT_CAMERA_START);
 
-        GameModel {
+        Ok(GameModel {
             should_close: false,
             map: game_map,
-            camera: GameCamera::new(),
+            camera: GameCamera::new(camera_start),
 
             input: InputState::new(),
             building: BuildingBehavior::new(),
             rng: rng,
-        }
+            time_accum: 0.0,
+            steps: 0,
+            recording: None,
+        })
+    }
+
+    pub fn load(path: &Path, seed: u64) -> Result<Self, MapLoadError> {
+        let file = MapFile::load(path)?;
+        GameModel::new(file, seed)
+    }
+
+    // Saves everything but the input
+    pub fn save<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut w = SaveWriter::new(out)?;
+        w.write_vec2_f32(self.camera.position)?;
+        self.rng.write_save(&mut w)?;
+        w.write_f32(self.time_accum)?;
+        w.write_u64(self.steps)?;
+        self.map.write_save(&mut w)
+    }
+
+    pub fn restore<R: Read>(input: R) -> Result<Self, SaveError> {
+        let mut r = SaveReader::new(input)?;
+        let camera_position = r.read_vec2_f32()?;
+        let rng = GameRng::read_save(&mut r)?;
+        let time_accum = r.read_f32()?;
+        let steps = r.read_u64()?;
+        let map = GameMap::read_save(&mut r)?;
+
+        Ok(GameModel {
+            should_close: false,
+            map: map,
+            camera: GameCamera {
+                position: camera_position,
+            },
+
+            input: InputState::new(),
+            building: BuildingBehavior::new(),
+            rng: rng,
+            time_accum: time_accum,
+            steps: steps,
+            recording: None,
+        })
     }
 
     pub fn keep_running(&self) -> bool {
@@ -165,10 +310,79 @@ impl GameModel {
         &self.input
     }
 
+    pub fn selected_structure(&self) -> Option<u32> {
+        self.building.selected_class(&self.map)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.rng.seed()
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // Call right after loading, so the map and seed are enough to replay it
+    pub fn start_recording(&mut self, map: &str) {
+        self.recording = Some(Recording::new(map, self.seed()));
+    }
+
+    pub fn finish_recording(&mut self) -> Option<Recording> {
+        let steps = self.steps;
+        self.recording.take().map(|mut recording| {
+            recording.set_end_step(steps);
+            recording
+        })
+    }
+
+    pub fn replay(&mut self, recording: &Recording) {
+        let mut events = recording.events().iter().peekable();
+        while self.steps < recording.end_step() {
+            // Apply all input that came in before this step
+            while events.peek().map(|e| e.step <= self.steps).unwrap_or(false) {
+                let event = events.next().unwrap();
+                self.apply_input(event.kind);
+            }
+
+            self.step();
+        }
+    }
+
     pub fn update(&mut self, delta: f32) {
-        self.building.update(&self.input, &mut self.map);
+        // The camera doesn't affect the simulation, so it can move smoothly with the frame rate
         self.camera.update(delta, &self.input);
-        self.map.update(delta, &mut self.rng);
+
+        // Step the simulation in fixed increments, so the same inputs always give the same results
+        self.time_accum += delta;
+        let mut steps = 0;
+        while self.time_accum >= TIMESTEP {
+            if steps == MAX_STEPS_PER_UPDATE {
+                self.time_accum = 0.0;
+                break;
+            }
+
+            self.time_accum -= TIMESTEP;
+            self.step();
+            steps += 1;
+        }
+    }
+
+    // Runs every step of the ticks, long ticks aren't cut short like slow frames
+    pub fn simulate(&mut self, ticks: u32, delta: f32) {
+        let mut accum = 0.0;
+        for _ in 0..ticks {
+            accum += delta;
+            while accum >= TIMESTEP {
+                accum -= TIMESTEP;
+                self.step();
+            }
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.building.update(&self.input, &mut self.map);
+        self.map.update(TIMESTEP, &mut self.rng);
+        self.steps += 1;
     }
 
     pub fn close(&mut self) {
@@ -177,9 +391,97 @@ impl GameModel {
 
     pub fn handle_keychange(&mut self, key: GameButton, state: bool) {
         self.input.set(key, state);
+        self.record(InputEventKind::Key(key, state));
     }
 
     pub fn handle_mouse_move(&mut self, position: Vector2<u32>) {
+        let previous = self.input.get_hover_tile();
         self.input.process_mouse(position, &self.camera);
+
+        // Only the tile matters to the simulation, so only record when that changes
+        let current = self.input.get_hover_tile();
+        if current != previous {
+            self.record(InputEventKind::Hover(current));
+        }
+    }
+
+    fn record(&mut self, kind: InputEventKind) {
+        if let Some(ref mut recording) = self.recording {
+            recording.push(InputEvent {
+                step: self.steps,
+                kind: kind,
+            });
+        }
+    }
+
+    fn apply_input(&mut self, kind: InputEventKind) {
+        match kind {
+            InputEventKind::Key(key, state) => self.input.set(key, state),
+            InputEventKind::Hover(tile) => self.input.hover_tile = tile,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use cgmath::Vector2;
+    use super::{GameButton, GameModel};
+
+    fn tap(model: &mut GameModel, button: GameButton) {
+        model.handle_keychange(button, true);
+        model.step();
+        model.handle_keychange(button, false);
+        model.step();
+    }
+
+    #[test]
+    fn palette_cycles_around() {
+        let mut model = GameModel::load(Path::new("assets/map.tmx"), 1).unwrap();
+        let buildable = model.map().tiles().defs().buildable();
+        assert_eq!(buildable.len(), 2);
+        assert_eq!(model.selected_structure(), Some(buildable[0]));
+
+        tap(&mut model, GameButton::NextStructure);
+        assert_eq!(model.selected_structure(), Some(buildable[1]));
+        tap(&mut model, GameButton::NextStructure);
+        assert_eq!(model.selected_structure(), Some(buildable[0]));
+        tap(&mut model, GameButton::PreviousStructure);
+        assert_eq!(model.selected_structure(), Some(buildable[1]));
+
+        // Holding the button down only moves once
+        model.handle_keychange(GameButton::NextStructure, true);
+        for _ in 0..10 {
+            model.step();
+        }
+        assert_eq!(model.selected_structure(), Some(buildable[0]));
+    }
+
+    #[test]
+    fn invalid_tiles_are_refused() {
+        let mut model = GameModel::load(Path::new("assets/map.tmx"), 1).unwrap();
+        let buildable = model.map().tiles().defs().buildable();
+
+        // Find a spot that's free to build on
+        let spot = {
+            let tiles = model.map().tiles();
+            (0..tiles.width()).flat_map(|x| (0..tiles.height()).map(move |y| Vector2::new(x, y)))
+                .find(|p| model.map().can_build(*p, buildable[0]))
+                .unwrap()
+        };
+        model.input.hover_tile = spot;
+        tap(&mut model, GameButton::Interact);
+        assert_eq!(model.map().tiles().get(spot.x, spot.y).unwrap().under_construction_class(), Some(buildable[0]));
+
+        // Something else can't go on top of it
+        tap(&mut model, GameButton::NextStructure);
+        tap(&mut model, GameButton::Interact);
+        assert_eq!(model.map().tiles().get(spot.x, spot.y).unwrap().under_construction_class(), Some(buildable[0]));
+
+        // Neither can anything go outside of the map, trying to just gets ignored
+        let outside = Vector2::new(model.map().tiles().width(), 0);
+        assert!(!model.map().can_build(outside, buildable[1]));
+        model.input.hover_tile = outside;
+        tap(&mut model, GameButton::Interact);
     }
 }
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use cgmath::Vector2;
+use items::ItemKind;
+use map::MapLoadError;
+use tmx::{MapFile, TmxObject};
+
+// Untyped objects in these groups spawn the group's kind of item, from before objects had types
+const SPAWNER_GROUPS: [(&'static str, ItemKind); 2] = [
+    ("Food Spawners", ItemKind::Food),
+    ("Mineral Spawners", ItemKind::Mineral),
+];
+
+// Picked by the type it's given in the editor
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectKind {
+    // Drops items in its area every now and then
+    FoodSpawn { kind: ItemKind, rate: f32, burst: u32 },
+    // Robots start out spread over its width
+    RobotSpawn { count: u32 },
+    CameraStart,
+    // Every one of these needs enough of the structure built in it to win
+    WinRegion { structure: String, count: u32 },
+    // Does something when a robot walks into it
+    Trigger { message: Option<String>, robots: u32, once: bool },
+}
+
+// In tiles, with the origin at the bottom left like the tiles
+#[derive(Debug, Clone)]
+pub struct MapObject {
+    // To point out where problems are
+    pub group: String,
+    pub index: usize,
+    pub kind: ObjectKind,
+    pub position: Vector2<f32>,
+    pub size: Vector2<f32>,
+}
+
+impl MapObject {
+    pub fn center(&self) -> Vector2<f32> {
+        self.position + self.size * 0.5
+    }
+}
+
+// Custom properties of an object, with defaults for missing ones
+struct Properties<'a> {
+    group: &'a str,
+    index: usize,
+    properties: &'a HashMap<String, String>,
+}
+
+impl<'a> Properties<'a> {
+    fn error(&self, name: &str, value: &str) -> MapLoadError {
+        MapLoadError::InvalidObjectProperty {
+            group: self.group.to_string(),
+            index: self.index,
+            property: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn get<T: FromStr>(&self, name: &str, default: T) -> Result<T, MapLoadError> {
+        match self.properties.get(name) {
+            Some(value) => value.parse().map_err(|_| self.error(name, value)),
+            None => Ok(default),
+        }
+    }
+
+    fn get_string(&self, name: &str) -> Option<String> {
+        self.properties.get(name).cloned()
+    }
+}
+
+fn parse_kind(object_type: &str, properties: &Properties, default_item: ItemKind)
+    -> Result<Option<ObjectKind>, MapLoadError>
+{
+    Ok(Some(match object_type {
+        "FoodSpawn" => {
+            // Residue and wrecks are left behind by things going wrong, not spawned
+            let kind_name = properties.get("kind", default_item.name().to_string())?;
+            let kind = match ItemKind::from_name(&kind_name) {
+                Some(kind @ ItemKind::Food) | Some(kind @ ItemKind::Mineral) => kind,
+                _ => return Err(properties.error("kind", &kind_name)),
+            };
+
+            let rate: f32 = properties.get("rate", 15.0)?;
+            if !rate.is_finite() || rate < 0.0 {
+                return Err(properties.error("rate", &rate.to_string()));
+            }
+
+            ObjectKind::FoodSpawn {
+                kind: kind,
+                rate: rate,
+                burst: properties.get("burst", 1)?,
+            }
+        },
+        "RobotSpawn" => ObjectKind::RobotSpawn {
+            count: properties.get("count", 1)?,
+        },
+        "CameraStart" => ObjectKind::CameraStart,
+        "WinRegion" => ObjectKind::WinRegion {
+            structure: properties.get_string("structure")
+                .ok_or_else(|| properties.error("structure", ""))?,
+            count: properties.get("count", 1)?,
+        },
+        "Trigger" => ObjectKind::Trigger {
+            message: properties.get_string("message"),
+            robots: properties.get("robots", 0)?,
+            once: properties.get("once", true)?,
+        },
+        _ => return Ok(None),
+    }))
+}
+
+fn load_object(object: &TmxObject, group: &str, index: usize, map_height: u32)
+    -> Result<MapObject, MapLoadError>
+{
+    let unsupported = || MapLoadError::UnsupportedObject {
+        group: group.to_string(),
+        index: index,
+    };
+    if !object.rectangle {
+        return Err(unsupported());
+    }
+
+    let spawner_group = SPAWNER_GROUPS.iter().find(|g| g.0 == group);
+    let object_type = match object.object_type {
+        Some(ref object_type) => &object_type[..],
+        None if spawner_group.is_some() => "FoodSpawn",
+        None => "",
+    };
+    let properties = Properties {
+        group: group,
+        index: index,
+        properties: &object.properties,
+    };
+    let default_item = spawner_group.map(|g| g.1).unwrap_or(ItemKind::Food);
+    let kind = parse_kind(object_type, &properties, default_item)?
+        .ok_or_else(|| MapLoadError::UnknownObjectType {
+            group: group.to_string(),
+            index: index,
+            object_type: object_type.to_string(),
+        })?;
+
+    // Everything but the camera start covers an area, spawners pick random spots in it and
+    // regions and triggers look for things inside it
+    if kind != ObjectKind::CameraStart {
+        if !object.width.is_finite() || object.width <= 0.0 {
+            return Err(properties.error("width", &object.width.to_string()));
+        }
+        if !object.height.is_finite() || object.height <= 0.0 {
+            return Err(properties.error("height", &object.height.to_string()));
+        }
+    }
+
+    // The editor has the origin at the top left, flip it around
+    let size = Vector2::new(object.width / 128.0, object.height / 128.0);
+    Ok(MapObject {
+        group: group.to_string(),
+        index: index,
+        kind: kind,
+        position: Vector2::new(object.x / 128.0, map_height as f32 - (object.y / 128.0) - size.y),
+        size: size,
+    })
+}
+
+// The groups are only there to keep things organized in the editor
+pub fn load(file: &MapFile) -> Result<Vec<MapObject>, MapLoadError> {
+    let mut objects = Vec::new();
+    for group in &file.object_groups {
+        for (i, object) in group.objects.iter().enumerate() {
+            objects.push(load_object(object, &group.name, i, file.map.height)?);
+        }
+    }
+
+    Ok(objects)
+}
@@ -0,0 +1,271 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use cgmath::Vector2;
+use tiles::Tiles;
+
+// Costs are scaled so diagonals can stay integers
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+#[derive(Debug, PartialEq, Eq)]
+struct OpenNode {
+    cost: u32, // cost so far plus the heuristic
+    index: usize,
+}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap, flip it so the cheapest node comes first
+        match other.cost.cmp(&self.cost) {
+            Ordering::Equal => other.index.cmp(&self.index),
+            ordering => ordering,
+        }
+    }
+}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn heuristic(from: Vector2<u32>, to: Vector2<u32>) -> u32 {
+    // Octile distance, admissible for 8-directional movement
+    let dx = (from.x as i64 - to.x as i64).abs() as u32;
+    let dy = (from.y as i64 - to.y as i64).abs() as u32;
+    let (min, max) = if dx < dy { (dx, dy) } else { (dy, dx) };
+    DIAGONAL_COST * min + STRAIGHT_COST * (max - min)
+}
+
+// Path through tile centers ending at the target, the start and target tiles may be solid
+pub fn find_path(tiles: &Tiles, start: Vector2<f32>, target: Vector2<f32>) -> Option<Vec<Vector2<f32>>> {
+    // Both ends need to be on the map
+    if start.x < 0.0 || start.y < 0.0 || target.x < 0.0 || target.y < 0.0 {
+        return None;
+    }
+    let start_tile = start.cast::<u32>();
+    let target_tile = target.cast::<u32>();
+    if tiles.get(start_tile.x, start_tile.y).is_none() || tiles.get(target_tile.x, target_tile.y).is_none() {
+        return None;
+    }
+
+    // If we're already in the right tile, we can just walk there
+    if start_tile == target_tile {
+        return Some(vec![target]);
+    }
+
+    let width = tiles.width() as usize;
+    let index_of = |pos: Vector2<u32>| pos.x as usize + pos.y as usize * width;
+    let pos_of = |index: usize| Vector2::new((index % width) as u32, (index / width) as u32);
+    let passable = |x: i64, y: i64| {
+        if x < 0 || y < 0 {
+            return false;
+        }
+        let pos = Vector2::new(x as u32, y as u32);
+        pos == target_tile || tiles.is_passable(pos.x, pos.y)
+    };
+
+    let amount = width * tiles.height() as usize;
+    let mut costs = vec![u32::max_value(); amount];
+    let mut came_from: Vec<Option<usize>> = vec![None; amount];
+    let mut open = BinaryHeap::new();
+
+    let start_index = index_of(start_tile);
+    let target_index = index_of(target_tile);
+    costs[start_index] = 0;
+    open.push(OpenNode { cost: heuristic(start_tile, target_tile), index: start_index });
+
+    while let Some(node) = open.pop() {
+        if node.index == target_index {
+            break;
+        }
+
+        let pos = pos_of(node.index);
+        let (x, y) = (pos.x as i64, pos.y as i64);
+        for dx in -1..2 {
+            for dy in -1..2 {
+                if dx == 0 && dy == 0 { continue; }
+                if !passable(x + dx, y + dy) { continue; }
+
+                // Don't allow cutting corners past solid tiles
+                let diagonal = dx != 0 && dy != 0;
+                if diagonal && (!passable(x + dx, y) || !passable(x, y + dy)) {
+                    continue;
+                }
+
+                let next = Vector2::new((x + dx) as u32, (y + dy) as u32);
+                let next_index = index_of(next);
+                let step = if diagonal { DIAGONAL_COST } else { STRAIGHT_COST };
+                let next_cost = costs[node.index] + step;
+                if next_cost < costs[next_index] {
+                    costs[next_index] = next_cost;
+                    came_from[next_index] = Some(node.index);
+                    open.push(OpenNode { cost: next_cost + heuristic(next, target_tile), index: next_index });
+                }
+            }
+        }
+    }
+
+    // If we never reached the target, there's no path
+    if came_from[target_index].is_none() {
+        return None;
+    }
+
+    // Walk back from the target to build up the waypoints
+    let mut waypoints = vec![target];
+    let mut current = came_from[target_index].unwrap();
+    while current != start_index {
+        waypoints.push(pos_of(current).cast::<f32>() + Vector2::new(0.5, 0.5));
+        current = came_from[current].unwrap();
+    }
+    waypoints.reverse();
+
+    Some(waypoints)
+}
+
+// Follows the same rules as `find_path`
+pub struct DistanceField {
+    width: u32,
+    costs: Vec<u32>,
+}
+
+impl DistanceField {
+    // None if the tile can't be reached
+    pub fn get(&self, tile: Vector2<u32>) -> Option<f32> {
+        let index = tile.x as usize + tile.y as usize * self.width as usize;
+        if tile.x >= self.width {
+            return None;
+        }
+        self.costs.get(index)
+            .and_then(|&cost| if cost == u32::max_value() { None } else { Some(cost as f32 / STRAIGHT_COST as f32) })
+    }
+}
+
+// Much cheaper than a path to every tile, solid tiles next to reachable ones count as targets
+pub fn distances_from(tiles: &Tiles, start: Vector2<f32>) -> Option<DistanceField> {
+    if start.x < 0.0 || start.y < 0.0 {
+        return None;
+    }
+    let start_tile = start.cast::<u32>();
+    if tiles.get(start_tile.x, start_tile.y).is_none() {
+        return None;
+    }
+
+    let width = tiles.width() as usize;
+    let index_of = |pos: Vector2<u32>| pos.x as usize + pos.y as usize * width;
+    let pos_of = |index: usize| Vector2::new((index % width) as u32, (index / width) as u32);
+    let passable = |x: i64, y: i64| x >= 0 && y >= 0 && tiles.is_passable(x as u32, y as u32);
+    let on_map = |x: i64, y: i64| x >= 0 && y >= 0 && x < tiles.width() as i64 && y < tiles.height() as i64;
+
+    let mut costs = vec![u32::max_value(); width * tiles.height() as usize];
+    let mut open = BinaryHeap::new();
+
+    let start_index = index_of(start_tile);
+    costs[start_index] = 0;
+    open.push(OpenNode { cost: 0, index: start_index });
+
+    while let Some(node) = open.pop() {
+        // Skip nodes we already found a shorter way to
+        if node.cost > costs[node.index] {
+            continue;
+        }
+
+        // Only the start tile can be left if it's solid
+        let pos = pos_of(node.index);
+        if node.index != start_index && !tiles.is_passable(pos.x, pos.y) {
+            continue;
+        }
+
+        let (x, y) = (pos.x as i64, pos.y as i64);
+        for dx in -1..2 {
+            for dy in -1..2 {
+                if dx == 0 && dy == 0 { continue; }
+                if !on_map(x + dx, y + dy) { continue; }
+
+                // Don't allow cutting corners past solid tiles
+                let diagonal = dx != 0 && dy != 0;
+                if diagonal && (!passable(x + dx, y) || !passable(x, y + dy)) {
+                    continue;
+                }
+
+                let next_index = index_of(Vector2::new((x + dx) as u32, (y + dy) as u32));
+                let step = if diagonal { DIAGONAL_COST } else { STRAIGHT_COST };
+                let next_cost = node.cost + step;
+                if next_cost < costs[next_index] {
+                    costs[next_index] = next_cost;
+                    open.push(OpenNode { cost: next_cost, index: next_index });
+                }
+            }
+        }
+    }
+
+    Some(DistanceField {
+        width: tiles.width(),
+        costs: costs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector2;
+    use tiles::Tiles;
+    use super::find_path;
+
+    fn center(x: u32, y: u32) -> Vector2<f32> {
+        Vector2::new(x as f32 + 0.5, y as f32 + 0.5)
+    }
+
+    #[test]
+    fn path_goes_around_walls() {
+        // y goes up, so the bottom row is y 0
+        let tiles = Tiles::from_rows(&[
+            ".....",
+            ".###.",
+            ".....",
+        ]);
+
+        let path = find_path(&tiles, center(2, 0), center(2, 2)).unwrap();
+        assert_eq!(*path.last().unwrap(), center(2, 2));
+        for waypoint in &path {
+            assert!(tiles.is_passable(waypoint.x as u32, waypoint.y as u32));
+        }
+        // Around the end of the wall and back, not through it or past its corners
+        assert_eq!(path.len(), 6);
+    }
+
+    #[test]
+    fn path_does_not_cut_corners() {
+        let tiles = Tiles::from_rows(&[
+            "..",
+            "#.",
+        ]);
+
+        // The diagonal would squeeze past the wall, so it has to go around
+        let path = find_path(&tiles, center(1, 0), center(0, 1)).unwrap();
+        assert_eq!(path, vec![center(1, 1), center(0, 1)]);
+    }
+
+    #[test]
+    fn path_takes_diagonals_in_the_open() {
+        let tiles = Tiles::from_rows(&[
+            "..",
+            "..",
+        ]);
+
+        let path = find_path(&tiles, center(0, 0), center(1, 1)).unwrap();
+        assert_eq!(path, vec![center(1, 1)]);
+    }
+
+    #[test]
+    fn walled_off_target_is_unreachable() {
+        let tiles = Tiles::from_rows(&[
+            "..#..",
+            "..#..",
+            "..#..",
+        ]);
+
+        assert_eq!(find_path(&tiles, center(0, 1), center(4, 1)), None);
+        // Off the map isn't reachable either
+        assert_eq!(find_path(&tiles, center(0, 1), center(7, 1)), None);
+    }
+}
@@ -0,0 +1,181 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use cgmath::Vector2;
+use enum_primitive::FromPrimitive;
+use model::GameButton;
+
+const HEADER: &'static str = "shift-inc recording 1";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputEventKind {
+    Key(GameButton, bool),
+    Hover(Vector2<u32>),
+}
+
+// Input that changed right before the numbered step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputEvent {
+    pub step: u64,
+    pub kind: InputEventKind,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Parse { line: usize, reason: String },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Io(ref e) =>
+                write!(f, "Couldn't read recording: {}", e),
+            ReplayError::Parse { line, ref reason } =>
+                write!(f, "Invalid recording on line {}: {}", line, reason),
+        }
+    }
+}
+
+impl Error for ReplayError {
+    fn description(&self) -> &str {
+        match *self {
+            ReplayError::Io(_) => "couldn't read recording",
+            ReplayError::Parse { .. } => "invalid recording",
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+// All input since loading, replays from the same map and seed
+pub struct Recording {
+    map: String,
+    seed: u64,
+    events: Vec<InputEvent>,
+    end_step: u64,
+}
+
+impl Recording {
+    pub fn new(map: &str, seed: u64) -> Self {
+        Recording {
+            map: map.to_string(),
+            seed: seed,
+            events: Vec::new(),
+            end_step: 0,
+        }
+    }
+
+    pub fn map(&self) -> &str {
+        &self.map
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    pub fn end_step(&self) -> u64 {
+        self.end_step
+    }
+
+    pub fn push(&mut self, event: InputEvent) {
+        self.events.push(event);
+    }
+
+    pub fn set_end_step(&mut self, step: u64) {
+        self.end_step = step;
+    }
+
+    // One line per event
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "{}", HEADER)?;
+        writeln!(out, "map {}", self.map)?;
+        writeln!(out, "seed {}", self.seed)?;
+        for event in &self.events {
+            match event.kind {
+                InputEventKind::Key(key, state) =>
+                    writeln!(out, "{} key {} {}", event.step, key as u32, if state { 1 } else { 0 })?,
+                InputEventKind::Hover(tile) =>
+                    writeln!(out, "{} hover {} {}", event.step, tile.x, tile.y)?,
+            }
+        }
+        writeln!(out, "{} end", self.end_step)
+    }
+
+    pub fn read<R: BufRead>(input: R) -> Result<Self, ReplayError> {
+        let mut map = None;
+        let mut seed = None;
+        let mut events = Vec::new();
+        let mut end_step = None;
+
+        for (i, line) in input.lines().enumerate() {
+            let line = line?;
+            let number = i + 1;
+            let error = |reason: &str| ReplayError::Parse { line: number, reason: reason.to_string() };
+
+            // The first line identifies the file
+            if i == 0 {
+                if line != HEADER {
+                    return Err(error("not a recording"));
+                }
+                continue;
+            }
+            if end_step.is_some() {
+                return Err(error("data after the end of the recording"));
+            }
+
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.is_empty() {
+                continue;
+            }
+
+            // Header values
+            if parts[0] == "map" {
+                map = Some(line["map".len()..].trim().to_string());
+                continue;
+            }
+            if parts[0] == "seed" && parts.len() == 2 {
+                seed = Some(parts[1].parse().map_err(|_| error("invalid seed"))?);
+                continue;
+            }
+
+            // Everything else is an event, starting with the step it happens at
+            if parts.len() < 2 {
+                return Err(error("unknown line"));
+            }
+            let step = parts[0].parse().map_err(|_| error("invalid step"))?;
+            let mut numbers = Vec::new();
+            for part in &parts[2..] {
+                numbers.push(part.parse::<u32>().map_err(|_| error("invalid number"))?);
+            }
+
+            match parts[1] {
+                "key" if numbers.len() == 2 && numbers[1] <= 1 => {
+                    let key = GameButton::from_u32(numbers[0]).ok_or_else(|| error("unknown key"))?;
+                    events.push(InputEvent { step: step, kind: InputEventKind::Key(key, numbers[1] == 1) });
+                },
+                "hover" if numbers.len() == 2 => {
+                    let tile = Vector2::new(numbers[0], numbers[1]);
+                    events.push(InputEvent { step: step, kind: InputEventKind::Hover(tile) });
+                },
+                "end" if numbers.is_empty() => end_step = Some(step),
+                _ => return Err(error("unknown event")),
+            }
+        }
+
+        Ok(Recording {
+            map: map.ok_or(ReplayError::Parse { line: 0, reason: "missing map".to_string() })?,
+            seed: seed.ok_or(ReplayError::Parse { line: 0, reason: "missing seed".to_string() })?,
+            events: events,
+            end_step: end_step.ok_or(ReplayError::Parse { line: 0, reason: "missing end".to_string() })?,
+        })
+    }
+}
@@ -0,0 +1,83 @@
+use std::io::{self, Read, Write};
+use rand::{Rng, StdRng};
+use save::{SaveWriter, SaveReader, SaveError};
+
+// Xorshift like rand's XorShiftRng, with its state out in the open for saves
+pub struct GameRng {
+    seed: u64,
+    state: [u32; 4],
+}
+
+pub fn random_seed() -> u64 {
+    StdRng::new().unwrap().gen()
+}
+
+impl GameRng {
+    pub fn from_seed(seed: u64) -> Self {
+        // Spread the seed out over the state with splitmix64, so similar seeds don't start out
+        // with similar states
+        let mut mix = seed;
+        let mut state = [0; 4];
+        for word in &mut state {
+            mix = mix.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = mix;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            *word = ((z ^ (z >> 31)) >> 32) as u32;
+        }
+
+        // Xorshift gets stuck on all zeroes
+        if state == [0; 4] {
+            state[0] = 1;
+        }
+
+        GameRng {
+            seed: seed,
+            state: state,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_u64(self.seed)?;
+        for word in &self.state {
+            w.write_u32(*word)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let seed = r.read_u64()?;
+        let mut state = [0; 4];
+        for word in &mut state {
+            *word = r.read_u32()?;
+        }
+
+        if state == [0; 4] {
+            return Err(SaveError::Corrupt("random number generator state is all zeroes".to_string()));
+        }
+
+        Ok(GameRng {
+            seed: seed,
+            state: state,
+        })
+    }
+}
+
+impl Rng for GameRng {
+    // Everything else Rng provides is built on top of this
+    fn next_u32(&mut self) -> u32 {
+        let x = self.state[0];
+        let t = x ^ (x << 11);
+        self.state[0] = self.state[1];
+        self.state[1] = self.state[2];
+        self.state[2] = self.state[3];
+        let w = self.state[3];
+        self.state[3] = w ^ (w >> 19) ^ (t ^ (t >> 8));
+        self.state[3]
+    }
+}
@@ -1,93 +1,336 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::{self, Read, Write};
+use std::mem;
 use cgmath::{Vector2, EuclideanVector};
-use rand::{StdRng, Rng};
+use rand::Rng;
+use rng::GameRng;
 use tiles::Tiles;
-use items::{Items, ItemState};
+use arena::{self, Arena, Handle};
+use items::{Item, ItemKind, Items, ItemState};
+use pathfinding::{self, DistanceField};
+use save::{SaveWriter, SaveReader, SaveError};
+
+// What to do at the work entry's target tile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WorkKind {
+    // Supply and build the construction
+    Build,
+    // Take the structure apart
+    Deconstruct,
+    // Bring the item to the tile
+    Haul(Handle),
+    // Get rid of the residue, the tile is where it was left
+    ClearDebris(Handle),
+    // Bring the wreck to the tile a fabricator takes items from
+    Repair(Handle),
+}
+
+// Higher priority work is done first
+pub const MAX_PRIORITY: u32 = 3;
+
+pub const MAX_ENERGY: f32 = 100.0;
+// Below this robots stop what they're doing to recharge
+const LOW_ENERGY: f32 = 25.0;
+// Per tile moved
+const MOVE_ENERGY: f32 = 1.0;
+// Per second spent building or taking apart
+const WORK_ENERGY: f32 = 2.0;
+// Per second at a charger
+const CHARGE_RATE: f32 = 20.0;
+// Per food item eaten
+const FOOD_ENERGY: f32 = 40.0;
+// Seconds before looking again if there was nowhere to recharge
+const RECHARGE_RETRY: f32 = 5.0;
+// How long a robot leaves items it couldn't reach alone before trying them again, in seconds
+const UNREACHABLE_RETRY: f32 = 10.0;
+// Per bit of energy used, robots are worn out at 1
+const WEAR_PER_ENERGY: f32 = 0.0005;
+// Chance per second for a worn out robot, less worn ones break down less
+const BREAKDOWN_RATE: f32 = 0.005;
+
+impl WorkKind {
+    pub fn default_priority(&self) -> u32 {
+        match *self {
+            WorkKind::Build | WorkKind::Deconstruct | WorkKind::Repair(_) => 1,
+            WorkKind::Haul(_) | WorkKind::ClearDebris(_) => 0,
+        }
+    }
+
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        match *self {
+            WorkKind::Build => w.write_u8(0),
+            WorkKind::Deconstruct => w.write_u8(1),
+            WorkKind::Haul(item) => {
+                w.write_u8(2)?;
+                item.write_save(w)
+            },
+            WorkKind::ClearDebris(item) => {
+                w.write_u8(3)?;
+                item.write_save(w)
+            },
+            WorkKind::Repair(item) => {
+                w.write_u8(4)?;
+                item.write_save(w)
+            },
+        }
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        Ok(match r.read_u8()? {
+            0 => WorkKind::Build,
+            1 => WorkKind::Deconstruct,
+            2 => WorkKind::Haul(Handle::read_save(r)?),
+            3 => WorkKind::ClearDebris(Handle::read_save(r)?),
+            4 => WorkKind::Repair(Handle::read_save(r)?),
+            v => return Err(SaveError::Corrupt(format!("{} isn't a valid work kind", v))),
+        })
+    }
+}
 
 #[derive(Debug)]
 pub struct WorkEntry {
-    id: Option<u32>, // TODO: Sparate work template
+    id: Option<Handle>, // TODO: Sparate work template
+    kind: WorkKind,
     target_tile: Vector2<u32>,
-    assigned_robot: Option<u32>,
+    priority: u32,
+    age: u64, // when it was published, lower is older
+    max_workers: u32,
+    assigned_robots: Vec<Handle>,
 }
 
 impl WorkEntry {
-    pub fn new(target_tile: Vector2<u32>) -> Self {
+    pub fn new(kind: WorkKind, target_tile: Vector2<u32>) -> Self {
         WorkEntry {
             id: None,
+            kind: kind,
             target_tile: target_tile,
-            assigned_robot: None,
+            priority: kind.default_priority(),
+            age: 0,
+            max_workers: 1,
+            assigned_robots: Vec::new(),
         }
     }
 
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = ::std::cmp::min(priority, MAX_PRIORITY);
+        self
+    }
+
+    pub fn with_max_workers(mut self, max_workers: u32) -> Self {
+        self.max_workers = ::std::cmp::max(max_workers, 1);
+        self
+    }
+
     fn assign(&mut self, robot: &mut Robot) {
-        self.assigned_robot = Some(robot.id.unwrap());
+        self.assigned_robots.push(robot.id.unwrap());
         robot.notify_of_work(self);
-        println!("Assigned {} at {:?} to {}", self.assigned_robot.unwrap(), self.target_tile, "TODO");
+        println!("Assigned {} at {:?} to {}", self.id(), self.target_tile, robot.id.unwrap());
+    }
+
+    fn free_slots(&self) -> u32 {
+        self.max_workers.saturating_sub(self.assigned_robots.len() as u32)
     }
 
-    fn id(&self) -> u32 {
+    fn id(&self) -> Handle {
         self.id.unwrap()
     }
 
-    fn target_tile(&self) -> Vector2<u32> {
+    pub fn kind(&self) -> WorkKind {
+        self.kind
+    }
+
+    pub fn target_tile(&self) -> Vector2<u32> {
         self.target_tile
     }
+
+    pub fn priority(&self) -> u32 {
+        self.priority
+    }
+
+    fn start_state(&self) -> RobotState {
+        match self.kind {
+            WorkKind::Build => RobotState::Building(self.target_tile),
+            WorkKind::Deconstruct => RobotState::Deconstructing(self.target_tile),
+            WorkKind::Haul(item) => RobotState::Hauling(item, self.target_tile),
+            WorkKind::ClearDebris(item) => RobotState::Clearing(item),
+            WorkKind::Repair(item) => RobotState::Hauling(item, self.target_tile),
+        }
+    }
+
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_option(self.id, |w, v| v.write_save(w))?;
+        self.kind.write_save(w)?;
+        w.write_vec2_u32(self.target_tile)?;
+        w.write_u32(self.priority)?;
+        w.write_u64(self.age)?;
+        w.write_u32(self.max_workers)?;
+        w.write_len(self.assigned_robots.len())?;
+        for robot in &self.assigned_robots {
+            robot.write_save(w)?;
+        }
+        Ok(())
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let id = r.read_option(Handle::read_save)?;
+        let kind = WorkKind::read_save(r)?;
+        let target_tile = r.read_vec2_u32()?;
+        let priority = r.read_u32()?;
+        let age = r.read_u64()?;
+        let max_workers = r.read_u32()?;
+        let mut assigned_robots = Vec::new();
+        for _ in 0..r.read_len()? {
+            assigned_robots.push(Handle::read_save(r)?);
+        }
+
+        Ok(WorkEntry {
+            id: id,
+            kind: kind,
+            target_tile: target_tile,
+            priority: priority,
+            age: age,
+            max_workers: max_workers,
+            assigned_robots: assigned_robots,
+        })
+    }
 }
 
 pub struct WorkQueue {
-    entries: Vec<Option<WorkEntry>>, // TODO: Share functionality with items
+    entries: Arena<WorkEntry>,
+    next_age: u64,
+    // Not saved, it's checked every step
+    structures_changed: bool,
 }
 
 impl WorkQueue {
     pub fn new() -> WorkQueue {
         WorkQueue {
-            entries: Vec::new(),
+            entries: Arena::new(),
+            next_age: 0,
+            structures_changed: false,
         }
     }
 
-    pub fn publish(&mut self, mut entry: WorkEntry) {
+    pub fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        self.entries.write_save(w, |entry, w| entry.write_save(w))?;
+        w.write_u64(self.next_age)
+    }
 
-        // Find an empty slot
-        for i in 0..self.entries.len() {
-            let slot = &mut self.entries[i];
-            if slot.is_some() { continue; }
+    pub fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        Ok(WorkQueue {
+            entries: Arena::read_save(r, WorkEntry::read_save)?,
+            next_age: r.read_u64()?,
+            structures_changed: false,
+        })
+    }
 
-            // Found a slot
-            entry.id = Some(i as u32);
-            println!("Publishing: {:?}", entry);
-            *slot = Some(entry);
-            return;
+    // Makes sure loaded work refers to itself and to tiles on the map
+    pub fn validate(&self, tiles: &Tiles) -> Result<(), SaveError> {
+        for (id, entry) in self.entries.iter() {
+            if entry.id != Some(id) {
+                return Err(SaveError::Corrupt(format!("work {} has the wrong id", id)));
+            }
+            tiles.check_saved_pos(entry.target_tile)?;
         }
 
-        // Couldn't find one, add a new one
-        entry.id = Some(self.entries.len() as u32);
+        Ok(())
+    }
+
+    pub fn publish(&mut self, mut entry: WorkEntry) -> Handle {
+        entry.age = self.next_age;
+        self.next_age += 1;
+        let id = self.entries.insert(entry);
+
+        let entry = self.entries.get_mut(id).unwrap();
+        entry.id = Some(id);
         println!("Publishing: {:?}", entry);
-        self.entries.push(Some(entry));
+
+        id
+    }
+
+    pub fn open(&self) -> Vec<Handle> {
+        self.entries.iter()
+            .filter(|&(_, e)| e.free_slots() != 0)
+            .map(|(id, _)| id)
+            .collect()
     }
 
-    pub fn request(&mut self) -> Option<&mut WorkEntry> {
-        self.entries.iter_mut()
-            .find(|e| e.is_some() && e.as_ref().unwrap().assigned_robot.is_none())
-            .map(|e| e.as_mut().unwrap())
+    // Wraps around to the lowest after the highest
+    pub fn bump_priority(&mut self, id: Handle) -> Option<u32> {
+        self.entries.get_mut(id).map(|entry| {
+            entry.priority = if entry.priority >= MAX_PRIORITY { 0 } else { entry.priority + 1 };
+            entry.priority
+        })
+    }
+
+    pub fn unassign(&mut self, id: Handle, robot: Handle) {
+        if let Some(entry) = self.entries.get_mut(id) {
+            entry.assigned_robots.retain(|r| *r != robot);
+            println!("Robot {} was unassigned from work entry {}", robot, id);
+        }
     }
 
-    pub fn finish(&mut self, id: u32) {
-        self.entries[id as usize] = None;
+    // Construction or deconstruction work on the tile
+    pub fn find(&self, tile: Vector2<u32>) -> Option<Handle> {
+        self.entries.iter()
+            .find(|&(_, e)| e.target_tile == tile && match e.kind {
+                WorkKind::Build | WorkKind::Deconstruct => true,
+                _ => false,
+            })
+            .map(|(id, _)| id)
+    }
+
+    // Returns the robots that were working on it
+    pub fn cancel(&mut self, id: Handle) -> Vec<Handle> {
+        let entry = self.entries.remove(id);
+        println!("Work entry {} was cancelled", id);
+        entry.map(|e| e.assigned_robots).unwrap_or_else(Vec::new)
+    }
+
+    pub fn finish(&mut self, id: Handle) {
+        if let Some(entry) = self.entries.remove(id) {
+            if entry.kind == WorkKind::Build || entry.kind == WorkKind::Deconstruct {
+                self.structures_changed = true;
+            }
+        }
         println!("Work entry {} was finished", id);
     }
 
-    pub fn get(&self, id: u32) -> &WorkEntry {
-        self.entries.get(id as usize).unwrap().as_ref().unwrap()
+    pub fn take_structures_changed(&mut self) -> bool {
+        ::std::mem::replace(&mut self.structures_changed, false)
+    }
+
+    pub fn get(&self, id: Handle) -> Option<&WorkEntry> {
+        self.entries.get(id)
+    }
+
+    pub fn is_hauling(&self, item: Handle) -> bool {
+        self.entries.iter().any(|(_, e)| e.kind == WorkKind::Haul(item) || e.kind == WorkKind::Repair(item))
+    }
+
+    pub fn hauled_to(&self, tile: Vector2<u32>) -> Vec<Handle> {
+        self.entries.iter()
+            .filter_map(|(_, e)| match e.kind {
+                WorkKind::Haul(item) if e.target_tile == tile => Some(item),
+                _ => None,
+            })
+            .collect()
     }
 }
 
-#[derive(Debug)]
-enum RobotState {
+#[derive(Debug, PartialEq)]
+pub enum RobotState {
     Waiting,
     Building(Vector2<u32>),
     Moving(Vector2<f32>, f32), // target, speed multiplier
     Sleep(f32), // time
-    PickUp(u32), // target item
+    PickUp(Handle), // target item
+    Deconstructing(Vector2<u32>),
+    Hauling(Handle, Vector2<u32>), // item, destination
+    Clearing(Handle), // residue item
+    Recharging(Vector2<u32>), // the tile next to a charger
+    Eating(Handle), // food item
 }
 
 impl RobotState {
@@ -98,15 +341,102 @@ impl RobotState {
             false
         }
     }
+
+    // The tile the state is about, if it's about one
+    fn tile(&self) -> Option<Vector2<u32>> {
+        match *self {
+            RobotState::Building(pos) | RobotState::Deconstructing(pos) |
+            RobotState::Hauling(_, pos) | RobotState::Recharging(pos) => Some(pos),
+            _ => None,
+        }
+    }
+
+    fn is_recharging(&self) -> bool {
+        match *self {
+            RobotState::Recharging(_) | RobotState::Eating(_) => true,
+            _ => false,
+        }
+    }
+
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        match *self {
+            RobotState::Waiting => w.write_u8(0),
+            RobotState::Building(pos) => {
+                w.write_u8(1)?;
+                w.write_vec2_u32(pos)
+            },
+            RobotState::Moving(pos, speed_multiplier) => {
+                w.write_u8(2)?;
+                w.write_vec2_f32(pos)?;
+                w.write_f32(speed_multiplier)
+            },
+            RobotState::Sleep(time) => {
+                w.write_u8(3)?;
+                w.write_f32(time)
+            },
+            RobotState::PickUp(target) => {
+                w.write_u8(4)?;
+                target.write_save(w)
+            },
+            RobotState::Deconstructing(pos) => {
+                w.write_u8(5)?;
+                w.write_vec2_u32(pos)
+            },
+            RobotState::Hauling(item, pos) => {
+                w.write_u8(6)?;
+                item.write_save(w)?;
+                w.write_vec2_u32(pos)
+            },
+            RobotState::Clearing(item) => {
+                w.write_u8(7)?;
+                item.write_save(w)
+            },
+            RobotState::Recharging(pos) => {
+                w.write_u8(8)?;
+                w.write_vec2_u32(pos)
+            },
+            RobotState::Eating(item) => {
+                w.write_u8(9)?;
+                item.write_save(w)
+            },
+        }
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        Ok(match r.read_u8()? {
+            0 => RobotState::Waiting,
+            1 => RobotState::Building(r.read_vec2_u32()?),
+            2 => {
+                let pos = r.read_vec2_f32()?;
+                RobotState::Moving(pos, r.read_f32()?)
+            },
+            3 => RobotState::Sleep(r.read_f32()?),
+            4 => RobotState::PickUp(Handle::read_save(r)?),
+            5 => RobotState::Deconstructing(r.read_vec2_u32()?),
+            6 => {
+                let item = Handle::read_save(r)?;
+                RobotState::Hauling(item, r.read_vec2_u32()?)
+            },
+            7 => RobotState::Clearing(Handle::read_save(r)?),
+            8 => RobotState::Recharging(r.read_vec2_u32()?),
+            9 => RobotState::Eating(Handle::read_save(r)?),
+            v => return Err(SaveError::Corrupt(format!("{} isn't a valid robot state", v))),
+        })
+    }
 }
 
 pub struct Robot {
-    id: Option<u32>, // TODO: Sparate robot template
+    id: Option<Handle>, // TODO: Sparate robot template
     position: Vector2<f32>,
-    assigned_work: Option<u32>, // id of the work
+    assigned_work: Option<Handle>, // id of the work
     current_state: RobotState,
     state_stack: Vec<RobotState>,
-    inventory: Option<u32>, // an item's id
+    inventory: Option<Handle>, // an item's id
+    promised: Option<(Vector2<u32>, ItemKind)>, // a resource we said we'd bring to a construction
+    energy: f32,
+    recharge_cooldown: f32, // time until we look for somewhere to recharge again
+    wear: f32,
+    unreachable: Vec<(Handle, f32)>, // items we couldn't reach and the time until we try again
 }
 
 impl Robot {
@@ -118,6 +448,11 @@ impl Robot {
             current_state: RobotState::Waiting,
             state_stack: Vec::new(),
             inventory: None,
+            promised: None,
+            energy: MAX_ENERGY,
+            recharge_cooldown: 0.0,
+            wear: 0.0,
+            unreachable: Vec::new(),
         }
     }
 
@@ -125,15 +460,111 @@ impl Robot {
         self.position
     }
 
+    pub fn state(&self) -> &RobotState {
+        &self.current_state
+    }
+
+    pub fn inventory(&self) -> Option<Handle> {
+        self.inventory
+    }
+
+    pub fn energy(&self) -> f32 {
+        self.energy
+    }
+
+    // From 0 to 1, worn robots break down more often
+    pub fn wear(&self) -> f32 {
+        self.wear
+    }
+
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_option(self.id, |w, v| v.write_save(w))?;
+        w.write_vec2_f32(self.position)?;
+        w.write_option(self.assigned_work, |w, v| v.write_save(w))?;
+        self.current_state.write_save(w)?;
+        w.write_len(self.state_stack.len())?;
+        for state in &self.state_stack {
+            state.write_save(w)?;
+        }
+        w.write_option(self.inventory, |w, v| v.write_save(w))?;
+        w.write_option(self.promised, |w, (pos, kind)| {
+            w.write_vec2_u32(pos)?;
+            kind.write_save(w)
+        })?;
+        w.write_f32(self.energy)?;
+        w.write_f32(self.recharge_cooldown)?;
+        w.write_f32(self.wear)?;
+        w.write_len(self.unreachable.len())?;
+        for &(id, time) in &self.unreachable {
+            id.write_save(w)?;
+            w.write_f32(time)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let id = r.read_option(Handle::read_save)?;
+        let position = r.read_vec2_f32()?;
+        let assigned_work = r.read_option(Handle::read_save)?;
+        let current_state = RobotState::read_save(r)?;
+        let mut state_stack = Vec::new();
+        for _ in 0..r.read_len()? {
+            state_stack.push(RobotState::read_save(r)?);
+        }
+        let inventory = r.read_option(Handle::read_save)?;
+        let promised = r.read_option(|r| {
+            let pos = r.read_vec2_u32()?;
+            Ok((pos, ItemKind::read_save(r)?))
+        })?;
+        let energy = r.read_f32()?;
+        let recharge_cooldown = r.read_f32()?;
+        let wear = r.read_f32()?;
+        let mut unreachable = Vec::new();
+        for _ in 0..r.read_len()? {
+            unreachable.push((Handle::read_save(r)?, r.read_f32()?));
+        }
+
+        Ok(Robot {
+            id: id,
+            position: position,
+            assigned_work: assigned_work,
+            current_state: current_state,
+            state_stack: state_stack,
+            inventory: inventory,
+            promised: promised,
+            energy: energy,
+            recharge_cooldown: recharge_cooldown,
+            wear: wear,
+            unreachable: unreachable,
+        })
+    }
+
     fn notify_of_work(&mut self, work: &WorkEntry) {
         self.assigned_work = Some(work.id());
     }
 
-    fn update(&mut self, delta: f32, items: &mut Items, tiles: &mut Tiles, work: &mut WorkQueue, rng: &mut StdRng) {
+    fn update(&mut self, delta: f32, items: &mut Items, tiles: &mut Tiles, work: &mut WorkQueue, rng: &mut GameRng) {
         // If we have an inventory, make sure it's following us
         if let Some(target) = self.inventory {
-            let mut item = items.get_mut(target).unwrap();
-            item.position = self.position + Vector2::new(0.0, -0.3);
+            if let Some(item) = items.get_mut(target) {
+                item.position = self.position + Vector2::new(0.0, -0.3);
+            } else {
+                // The item doesn't exist anymore, we lost it
+                self.inventory = None;
+            }
+        }
+
+        // Give items we couldn't reach another chance after a while, the way might be clear now
+        for entry in &mut self.unreachable {
+            entry.1 -= delta;
+        }
+        self.unreachable.retain(|&(_, time)| time > 0.0);
+
+        // If we're running low, drop what we're doing for now and go recharge
+        self.recharge_cooldown -= delta;
+        if self.energy < LOW_ENERGY && self.recharge_cooldown <= 0.0 && !self.is_recharging() {
+            self.start_recharging(items, tiles);
         }
 
         // Update for the specific state
@@ -142,12 +573,14 @@ impl Robot {
                 // If we have work, find something to do
                 if let Some(work_id) = self.assigned_work {
                     // First, get the position where the work is
-                    let pos = work.get(work_id).target_tile();
-
-                    // Set the state to building and tell the robot to move there
-                    self.current_state = RobotState::Building(pos);
-
-                    println!("Robot {} switched to Building", self.id.unwrap());
+                    if let Some(entry) = work.get(work_id) {
+                        // Switch to the state for the kind of work, it takes care of getting there
+                        self.current_state = entry.start_state();
+                        println!("Robot {} switched to {:?}", self.id.unwrap(), self.current_state);
+                    } else {
+                        // The work is gone, forget about it
+                        self.assigned_work = None;
+                    }
                 } else {
                     // Clamp the position
                     let tile = self.position.cast::<u32>().cast::<f32>();
@@ -159,47 +592,85 @@ impl Robot {
 
                     // Finally, start the movement
                     self.push_state(RobotState::Sleep(1.0));
-                    self.push_state(RobotState::Moving(new_pos, 0.25));
+                    self.move_to(new_pos, 0.25, tiles);
                     println!("Robot {} is idling", self.id.unwrap());
                 }
             },
             RobotState::Building(pos) => {
-                let mut tile = tiles.get_mut(pos.x, pos.y).unwrap();
-                assert!(tile.is_under_construction());
+                let target = pos.cast::<f32>() + Vector2::new(0.5, 0.5);
+                let on_site = self.position.cast::<u32>() == pos;
 
-                // Check if the building needs more resources
-                if tile.construction_needs_resources() {
-                    // This will be needed if more than one robot can construct a thing at a time
-                    //tile.promise_resource();
+                // Another robot might have finished it already
+                if !tiles.get(pos.x, pos.y).unwrap().is_under_construction() {
+                    self.release_items(items);
+                    self.promised = None;
+                    self.assigned_work = None;
+                    self.reset_state(RobotState::Waiting);
+                    println!("Robot {} found its building already finished", self.id.unwrap());
+                    return;
+                }
 
-                    // Check if we currently have an item
+                // Check if the building needs more resources
+                if tiles.get(pos.x, pos.y).unwrap().construction_needs_resources() {
                     if self.inventory.is_some() {
                         // We have a resource, check if we're already at the building site
-                        if self.position.cast::<u32>() == pos {
-                            // We are, deposit the resource
-                            items.remove(self.inventory.unwrap());
+                        if on_site {
+                            // We are, deposit the resource if it's still needed
+                            let id = self.inventory.unwrap();
+                            let kind = items.get(id).unwrap().kind;
+                            if tiles.get_mut(pos.x, pos.y).unwrap().apply_resource(kind) {
+                                // Using the item fulfilled our promise
+                                items.remove(id);
+                                self.promised = None;
+                            } else {
+                                // Someone beat us to it, take the promise back since we can't keep it
+                                let item = items.get_mut(id).unwrap();
+                                item.state = ItemState::Falling;
+                                item.claimed = false;
+                                self.drop_promise(tiles);
+                            }
                             self.inventory = None;
-                            tile.apply_resource();
                         } else {
-                            // We're not, move there
-                            self.push_state(RobotState::Moving(pos.cast::<f32>() + Vector2::new(0.5, 0.5), 1.0));
+                            // We're not, move there, if we can't reach it give the work back
+                            if !self.move_to(target, 1.0, tiles) {
+                                self.abandon_work(items, tiles, work);
+                            }
                         }
-                    } else {
+                    } else if let Some((_, kind)) = self.promised {
+                        // We promised a resource but lost it on the way, look for another one
+                        if let Some(claimed) = items.claim_resource(self.position, kind, tiles, &self.unreachable_items()) {
+                            self.push_state(RobotState::PickUp(claimed));
+                        } else {
+                            self.drop_promise(tiles);
+                            self.push_state(RobotState::Sleep(1.0));
+                        }
+                    } else if let Some(kind) = tiles.get(pos.x, pos.y).unwrap().unpromised_resource() {
                         // Find a resource to claim
                         // TODO: Move this to a start behavior for the PickUp state
-                        let claimed = items.claim_resource(self.position);
+                        let claimed = items.claim_resource(self.position, kind, tiles, &self.unreachable_items());
                         println!("Robot {} claimed item {:?}", self.id.unwrap(), claimed);
 
-                        if claimed.is_none() {
+                        if let Some(claimed) = claimed {
+                            // If we did find something, tell the others and move to pick it up
+                            tiles.get_mut(pos.x, pos.y).unwrap().promise_resource(kind);
+                            self.promised = Some((pos, kind));
+                            self.push_state(RobotState::PickUp(claimed));
+                        } else {
                             // If we didn't find anything, just wait a bit
                             self.push_state(RobotState::Sleep(1.0));
-                        } else {
-                            // If we did find something, move to pick it up
-                            self.push_state(RobotState::PickUp(claimed.unwrap()));
+                        }
+                    } else {
+                        // Everything that's needed is on its way, wait on site to help build
+                        if on_site {
+                            self.push_state(RobotState::Sleep(0.5));
+                        } else if !self.move_to(target, 1.0, tiles) {
+                            self.abandon_work(items, tiles, work);
                         }
                     }
-                } else {
-                    let finished = tile.apply_build_time(delta);
+                } else if on_site {
+                    // Every robot on site builds, so more robots finish it faster
+                    self.use_energy(WORK_ENERGY * delta);
+                    let finished = tiles.get_mut(pos.x, pos.y).unwrap().apply_build_time(delta);
 
                     if finished {
                         // Mark the work as done
@@ -208,20 +679,141 @@ impl Robot {
                         self.reset_state(RobotState::Waiting);
                         println!("Robot {} finished building", self.id.unwrap());
                     }
+                } else if !self.move_to(target, 1.0, tiles) {
+                    self.abandon_work(items, tiles, work);
+                }
+            },
+            RobotState::Deconstructing(pos) => {
+                let target = pos.cast::<f32>() + Vector2::new(0.5, 0.5);
+
+                if self.position.cast::<u32>() == pos {
+                    // We're at the structure, take it apart
+                    self.use_energy(WORK_ENERGY * delta);
+                    let class = tiles.get(pos.x, pos.y).unwrap().class();
+                    let refund = tiles.defs().get(class).cost.clone();
+                    let empty = tiles.defs().empty();
+                    let finished = tiles.get_mut(pos.x, pos.y).unwrap().apply_deconstruct_time(delta, empty);
+
+                    if finished {
+                        // Give back what the structure was built with
+                        for kind in ItemKind::all() {
+                            for _ in 0..refund.get(*kind) {
+                                items.add(Item::new(*kind, target));
+                            }
+                        }
+
+                        work.finish(self.assigned_work.unwrap());
+                        self.assigned_work = None;
+                        self.reset_state(RobotState::Waiting);
+                        println!("Robot {} finished deconstructing", self.id.unwrap());
+                    }
+                } else {
+                    // We're not there yet, if we can't reach it give the work back
+                    if !self.move_to(target, 1.0, tiles) {
+                        self.abandon_work(items, tiles, work);
+                    }
+                }
+            },
+            RobotState::Hauling(target, pos) => {
+                if self.inventory == Some(target) {
+                    if self.position.cast::<u32>() == pos {
+                        // We're there, drop the item off and leave it for whoever needs it
+                        let item = items.get_mut(target).unwrap();
+                        item.state = ItemState::Falling;
+                        item.claimed = false;
+                        self.inventory = None;
+
+                        work.finish(self.assigned_work.unwrap());
+                        self.assigned_work = None;
+                        self.reset_state(RobotState::Waiting);
+                        println!("Robot {} finished hauling", self.id.unwrap());
+                    } else {
+                        // Bring it there, if we can't reach it give the work back
+                        let destination = pos.cast::<f32>() + Vector2::new(0.5, 0.5);
+                        if !self.move_to(destination, 1.0, tiles) {
+                            self.abandon_work(items, tiles, work);
+                        }
+                    }
+                } else if items.get(target).map(Robot::can_haul).unwrap_or(false) {
+                    // The item's still lying there, claim it and go get it
+                    items.get_mut(target).unwrap().claimed = true;
+                    self.push_state(RobotState::PickUp(target));
+                } else {
+                    // The item's gone, someone else took it or we couldn't get to it, there's
+                    // nothing left to do
+                    work.finish(self.assigned_work.unwrap());
+                    self.assigned_work = None;
+                    self.reset_state(RobotState::Waiting);
+                    println!("Robot {} couldn't haul item {}", self.id.unwrap(), target);
+                }
+            },
+            RobotState::Clearing(target) => {
+                if self.inventory == Some(target) {
+                    // We've got it, get rid of it
+                    items.remove(target);
+                    self.inventory = None;
+
+                    work.finish(self.assigned_work.unwrap());
+                    self.assigned_work = None;
+                    self.reset_state(RobotState::Waiting);
+                    println!("Robot {} finished clearing residue", self.id.unwrap());
+                } else if items.get(target).map(|i| i.claimed && i.state != ItemState::Carried).unwrap_or(false) {
+                    // The residue's still waiting for us, go get it
+                    self.push_state(RobotState::PickUp(target));
+                } else {
+                    // The residue's gone or we couldn't get to it, it'll be given out again
+                    work.finish(self.assigned_work.unwrap());
+                    self.assigned_work = None;
+                    self.reset_state(RobotState::Waiting);
+                    println!("Robot {} couldn't clear item {}", self.id.unwrap(), target);
+                }
+            },
+            RobotState::Recharging(pos) => {
+                if self.position.cast::<u32>() == pos {
+                    // We're at the charger, stay until we're full and then get back to work
+                    self.energy += CHARGE_RATE * delta;
+                    if self.energy >= MAX_ENERGY {
+                        self.energy = MAX_ENERGY;
+                        self.pop_state();
+                        println!("Robot {} finished recharging", self.id.unwrap());
+                    }
+                } else if !self.move_to(pos.cast::<f32>() + Vector2::new(0.5, 0.5), 1.0, tiles) {
+                    // The charger can't be reached anymore, look again later
+                    self.recharge_cooldown = RECHARGE_RETRY;
+                    self.pop_state();
+                }
+            },
+            RobotState::Eating(target) => {
+                if self.inventory == Some(target) {
+                    items.remove(target);
+                    self.inventory = None;
+                    self.energy = (self.energy + FOOD_ENERGY).min(MAX_ENERGY);
+                    self.pop_state();
+                    println!("Robot {} ate item {}", self.id.unwrap(), target);
+                } else if items.get(target).map(|i| i.claimed && i.state != ItemState::Carried).unwrap_or(false) {
+                    // The food's still waiting for us, go get it
+                    self.push_state(RobotState::PickUp(target));
+                } else {
+                    // We couldn't get to it, go back to what we were doing until we look again
+                    self.recharge_cooldown = RECHARGE_RETRY;
+                    self.pop_state();
                 }
             },
             RobotState::Moving(pos, speed_multiplier) => {
-                // Check how far we still need to move
+                // Check how far we still need to move, robots without energy left crawl
                 let difference = pos - self.position;
                 let move_speed = delta * 0.75;
+                let speed_multiplier = if self.energy <= 0.0 { speed_multiplier * 0.5 } else { speed_multiplier };
 
                 // Check if this frame we'll be there
                 if difference.magnitude2() <= move_speed*move_speed {
+                    self.use_energy(difference.magnitude2().sqrt() * MOVE_ENERGY);
                     self.position = pos;
                     self.pop_state();
                 } else {
                     // If not, move the distance we can
                     let direction = difference.normalize();
+                    self.use_energy(move_speed * speed_multiplier * MOVE_ENERGY);
                     self.position = self.position + (direction * move_speed * speed_multiplier);
                 }
             }
@@ -234,8 +826,15 @@ impl Robot {
                 }
             },
             RobotState::PickUp(target) => {
+                // If the item doesn't exist anymore, we can't pick it up
+                let item = if let Some(item) = items.get_mut(target) {
+                    item
+                } else {
+                    self.pop_state();
+                    return;
+                };
+
                 // Check if we're within pickup range of the target
-                let item = items.get_mut(target).unwrap();
                 let pos = item.position() + Vector2::new(0.0, 0.3); // A bit above because it looks better
                 let distance = pos - self.position;
 
@@ -245,12 +844,180 @@ impl Robot {
                     self.pop_state();
                 } else {
                     // We aren't close enough, move to it
-                    self.push_state(RobotState::Moving(pos, 1.0));
+                    if !self.move_to(pos, 1.0, tiles) {
+                        // We can't reach it, let someone else have it and look for another one
+                        item.claimed = false;
+                        self.unreachable.push((target, UNREACHABLE_RETRY));
+                        self.pop_state();
+                        self.push_state(RobotState::Sleep(1.0));
+                    }
                 }
             }
         }
     }
 
+    // Working also wears the robot out
+    fn use_energy(&mut self, amount: f32) {
+        self.energy = (self.energy - amount).max(0.0);
+        self.wear = (self.wear + amount * WEAR_PER_ENERGY).min(1.0);
+    }
+
+    // The more worn out, the likelier
+    fn breaks_down(&mut self, delta: f32, rng: &mut GameRng) -> bool {
+        self.wear > 0.0 && rng.gen_range(0.0, 1.0) < self.wear * BREAKDOWN_RATE * delta
+    }
+
+    // Lets go of everything and gives the work back, leaving a wreck behind
+    fn break_down(mut self, items: &mut Items, tiles: &mut Tiles, work: &mut WorkQueue) {
+        self.release_items(items);
+        self.drop_promise(tiles);
+        if let Some(work_id) = self.assigned_work.take() {
+            // Residue we were clearing gets its own work again once it comes to rest
+            let clearing = work.get(work_id)
+                .map(|e| match e.kind { WorkKind::ClearDebris(_) => true, _ => false })
+                .unwrap_or(false);
+            if clearing {
+                work.finish(work_id);
+            } else {
+                work.unassign(work_id, self.id.unwrap());
+            }
+        }
+
+        items.add(Item::new(ItemKind::Wreck, self.position));
+        println!("Robot {} broke down", self.id.unwrap());
+    }
+
+    fn is_recharging(&self) -> bool {
+        self.current_state.is_recharging() || self.state_stack.iter().any(|s| s.is_recharging())
+    }
+
+    // Goes to the closest charger or food item, food only if our hands are free
+    fn start_recharging(&mut self, items: &mut Items, tiles: &Tiles) {
+        let distances = pathfinding::distances_from(tiles, self.position);
+        let distance = |tile: Vector2<u32>| distances.as_ref().and_then(|d| d.get(tile));
+
+        let mut charger: Option<(Vector2<u32>, f32)> = None;
+        for spot in charge_spots(tiles) {
+            if let Some(d) = distance(spot) {
+                if charger.map(|(_, best)| d < best).unwrap_or(true) {
+                    charger = Some((spot, d));
+                }
+            }
+        }
+
+        let food = if self.inventory.is_none() {
+            items.nearest_unclaimed(self.position, ItemKind::Food, tiles, &self.unreachable_items())
+                .and_then(|id| distance(items.get(id).unwrap().position.cast()).map(|d| (id, d)))
+        } else {
+            None
+        };
+
+        let state = match (charger, food) {
+            (Some((_, charger_distance)), Some((id, food_distance))) if food_distance < charger_distance => {
+                items.get_mut(id).unwrap().claimed = true;
+                RobotState::Eating(id)
+            },
+            (Some((spot, _)), _) => RobotState::Recharging(spot),
+            (None, Some((id, _))) => {
+                items.get_mut(id).unwrap().claimed = true;
+                RobotState::Eating(id)
+            },
+            (None, None) => {
+                self.recharge_cooldown = RECHARGE_RETRY;
+                return;
+            },
+        };
+
+        // Where we were walking won't make sense from the charger, the job's state will find
+        // its way again when we get back to it
+        while let RobotState::Moving(..) = self.current_state {
+            if self.state_stack.is_empty() {
+                break;
+            }
+            self.pop_state();
+        }
+
+        println!("Robot {} is low on energy, switching to {:?}", self.id.unwrap(), state);
+        self.push_state(state);
+    }
+
+    // Hauled items aren't claimed until they're picked up, constructions can use them until then
+    fn can_haul(item: &Item) -> bool {
+        item.state == ItemState::Static && !item.claimed && item.kind != ItemKind::Residue
+    }
+
+    // Pushes the moves to get to the target, false if it can't be reached
+    fn move_to(&mut self, target: Vector2<f32>, speed_multiplier: f32, tiles: &Tiles) -> bool {
+        let path = if let Some(path) = pathfinding::find_path(tiles, self.position, target) {
+            path
+        } else {
+            println!("Robot {} can't reach {:?}", self.id.unwrap(), target);
+            return false;
+        };
+
+        // The stack is last in first out, so push the last waypoint first
+        for waypoint in path.into_iter().rev() {
+            self.push_state(RobotState::Moving(waypoint, speed_multiplier));
+        }
+
+        true
+    }
+
+    // Items we recently couldn't reach, which we shouldn't claim again just yet
+    fn unreachable_items(&self) -> Vec<Handle> {
+        self.unreachable.iter().map(|&(id, _)| id).collect()
+    }
+
+    // Lets go of carried and claimed items so they can be used elsewhere
+    fn release_items(&mut self, items: &mut Items) {
+        // The item might have been removed while we were carrying it
+        if let Some(target) = self.inventory.take() {
+            if let Some(item) = items.get_mut(target) {
+                item.state = ItemState::Falling;
+                item.claimed = false;
+            }
+        }
+
+        for state in self.state_stack.iter().chain(Some(&self.current_state)) {
+            if let RobotState::PickUp(target) | RobotState::Eating(target) = *state {
+                if let Some(item) = items.get_mut(target) {
+                    item.claimed = false;
+                }
+            }
+        }
+    }
+
+    fn drop_promise(&mut self, tiles: &mut Tiles) {
+        if let Some((pos, kind)) = self.promised.take() {
+            if let Some(tile) = tiles.get_mut(pos.x, pos.y) {
+                if tile.is_under_construction() {
+                    tile.unpromise_resource(kind);
+                }
+            }
+        }
+    }
+
+    fn abandon_work(&mut self, items: &mut Items, tiles: &mut Tiles, work: &mut WorkQueue) {
+        self.release_items(items);
+        self.drop_promise(tiles);
+
+        // Give the work back to the queue and wait a bit before accepting new work
+        work.unassign(self.assigned_work.unwrap(), self.id.unwrap());
+        self.assigned_work = None;
+        self.reset_state(RobotState::Waiting);
+        self.push_state(RobotState::Sleep(2.0));
+        println!("Robot {} abandoned its work", self.id.unwrap());
+    }
+
+    // The work doesn't exist anymore
+    fn cancel_work(&mut self, items: &mut Items) {
+        self.release_items(items);
+        self.promised = None; // the construction is gone along with the work
+        self.assigned_work = None;
+        self.reset_state(RobotState::Waiting);
+        println!("Robot {}'s work was cancelled", self.id.unwrap());
+    }
+
     fn push_state(&mut self, mut state: RobotState) {
         println!("State being pushed: {:?}", state);
         ::std::mem::swap(&mut state, &mut self.current_state);
@@ -267,64 +1034,178 @@ impl Robot {
     }
 }
 
+// The open tiles around finished chargers
+fn charge_spots(tiles: &Tiles) -> Vec<Vector2<u32>> {
+    let mut spots = Vec::new();
+    tiles.for_each(|x, y, tile| {
+        if tile.is_under_construction() || !tiles.defs().get(tile.class()).charger {
+            return;
+        }
+
+        let neighbours = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+        for &(nx, ny) in &neighbours {
+            let spot = Vector2::new(nx, ny);
+            if tiles.is_passable(nx, ny) && !spots.contains(&spot) {
+                spots.push(spot);
+            }
+        }
+    });
+    spots
+}
+
 pub struct Robots {
-    robots: Vec<Robot>,
+    robots: Arena<Robot>,
+    // From the tiles waiting robots stood on, kept until the tiles change
+    distances: HashMap<(u32, u32), DistanceField>,
 }
 
 impl Robots {
     pub fn new() -> Self {
         Robots {
-            robots: Vec::new(),
+            robots: Arena::new(),
+            distances: HashMap::new(),
         }
     }
 
-    pub fn amount(&mut self) -> usize {
+    pub fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        self.robots.write_save(w, |robot, w| robot.write_save(w))
+    }
+
+    pub fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        Ok(Robots {
+            robots: Arena::read_save(r, Robot::read_save)?,
+            distances: HashMap::new(),
+        })
+    }
+
+    // Makes sure loaded robots refer to themselves and are on the map, with everything they
+    // work on on the map too
+    pub fn validate(&self, tiles: &Tiles) -> Result<(), SaveError> {
+        for (id, robot) in self.robots.iter() {
+            if robot.id != Some(id) {
+                return Err(SaveError::Corrupt(format!("robot {} has the wrong id", id)));
+            }
+            if robot.position.x < 0.0 || robot.position.y < 0.0 {
+                return Err(SaveError::Corrupt(format!("robot {} is outside of the map", id)));
+            }
+            tiles.check_saved_pos(robot.position.cast())?;
+
+            let states = robot.state_stack.iter().chain(Some(&robot.current_state));
+            for pos in states.filter_map(|s| s.tile()).chain(robot.promised.map(|(pos, _)| pos)) {
+                tiles.check_saved_pos(pos)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn amount(&self) -> usize {
         self.robots.len()
     }
 
-    pub fn add(&mut self, mut robot: Robot) {
-        robot.id = Some(self.robots.len() as u32);
-        self.robots.push(robot);
+    pub fn add(&mut self, robot: Robot) -> Handle {
+        let id = self.robots.insert(robot);
+        self.robots.get_mut(id).unwrap().id = Some(id);
+        id
+    }
+
+    pub fn iter<'a>(&'a self) -> arena::Iter<'a, Robot> {
+        self.robots.iter()
     }
 
     pub fn for_each<F: FnMut(&Robot)>(&self, mut f: F) { // TODO: Perhaps just return a vec
-        for robot in &self.robots {
-            //if let &Some(ref robot) = robot {
+        for (_, robot) in self.robots.iter() {
             f(robot);
-            //}
         }
     }
 
-    pub fn update(&mut self, delta: f32, items: &mut Items, tiles: &mut Tiles, work: &mut WorkQueue, rng: &mut StdRng) {
-        self.assign_work(work);
+    pub fn update(&mut self, delta: f32, items: &mut Items, tiles: &mut Tiles, work: &mut WorkQueue, rng: &mut GameRng) {
+        self.assign_work(work, tiles);
 
         // Now that all work is assigned, update the robots
-        for robot in &mut self.robots {
+        let mut broken = Vec::new();
+        for (id, robot) in self.robots.iter_mut() {
             robot.update(delta, items, tiles, work, rng);
+            if robot.breaks_down(delta, rng) {
+                broken.push(id);
+            }
+        }
+
+        for id in broken {
+            let robot = self.robots.remove(id).unwrap();
+            robot.break_down(items, tiles, work);
+        }
+    }
+
+    pub fn cancel_work(&mut self, id: Handle, items: &mut Items) {
+        if let Some(robot) = self.robots.get_mut(id) {
+            robot.cancel_work(items);
         }
     }
 
-    fn assign_work(&mut self, work: &mut WorkQueue) {
+    // Call whenever a tile changes between passable and solid
+    pub fn forget_distances(&mut self) {
+        self.distances.clear();
+    }
+
+    // Most important work first, to the closest robot that can reach it, then the oldest
+    pub fn assign_work(&mut self, work: &mut WorkQueue, tiles: &Tiles) {
+        let open = work.open();
+        if open.is_empty() {
+            return;
+        }
+
         // Get all waiting robots
-        let mut waiting_robots: Vec<_> = self.robots.iter_mut().filter(|r| r.current_state.is_waiting()).collect();
+        let waiting_robots: Vec<_> = self.robots.iter()
+            .filter(|&(_, r)| r.current_state.is_waiting() && r.assigned_work.is_none())
+            .map(|(id, r)| (id, r.position))
+            .collect();
+        if waiting_robots.is_empty() {
+            return;
+        }
 
-        // Go over all available work items
-        let mut entries = 0;
-        while waiting_robots.len() != 0 {
-            let work = {
-                if let Some(work) = work.request() {
-                    work
-                } else {
-                    break;
+        // Find out how far every robot would have to walk for every work entry, only keeping
+        // the distances from where robots are still waiting around
+        let mut previous = mem::replace(&mut self.distances, HashMap::new());
+        let mut candidates = Vec::new();
+        for (robot_index, &(_, position)) in waiting_robots.iter().enumerate() {
+            let tile = position.cast::<u32>();
+            let key = (tile.x, tile.y);
+            if let Entry::Vacant(entry) = self.distances.entry(key) {
+                match previous.remove(&key).or_else(|| pathfinding::distances_from(tiles, position)) {
+                    Some(distances) => { entry.insert(distances); },
+                    None => continue,
                 }
-            };
+            }
+            let distances = &self.distances[&key];
 
-            // Assign the first available robot
-            // TODO: Find closest
-            let robot = waiting_robots.swap_remove(0);
+            for (work_index, id) in open.iter().enumerate() {
+                let entry = work.get(*id).unwrap();
+                if let Some(distance) = distances.get(entry.target_tile()) {
+                    candidates.push((entry.priority, distance, entry.age, robot_index, work_index));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then(a.1.partial_cmp(&b.1).unwrap())
+                .then(a.2.cmp(&b.2))
+        });
+
+        // Greedily hand out the best matches, skipping robots and work that are already taken
+        let mut robot_taken = vec![false; waiting_robots.len()];
+        let mut work_slots: Vec<_> = open.iter().map(|id| work.get(*id).unwrap().free_slots()).collect();
+        let mut entries = 0;
+        for (_, _, _, robot_index, work_index) in candidates {
+            if robot_taken[robot_index] || work_slots[work_index] == 0 {
+                continue;
+            }
+            robot_taken[robot_index] = true;
+            work_slots[work_index] -= 1;
 
             // Assign the robot to the work item
-            work.assign(robot);
+            let robot = self.robots.get_mut(waiting_robots[robot_index].0).unwrap();
+            work.entries.get_mut(open[work_index]).unwrap().assign(robot);
             entries += 1;
         }
 
@@ -333,3 +1214,387 @@ impl Robots {
         }
     }
 }
+
+#[cfg(test)]
+impl Robots {
+    // Adds a robot that's working on the entry, carrying an item it promised to the construction
+    pub fn add_carrying(&mut self, work: &mut WorkQueue, entry: Handle, item: Handle, kind: ItemKind) -> Handle {
+        let pos = work.get(entry).unwrap().target_tile;
+        let mut robot = Robot::new(pos.cast() + Vector2::new(0.5, 0.5));
+        robot.current_state = RobotState::Building(pos);
+        robot.inventory = Some(item);
+        robot.promised = Some((pos, kind));
+
+        let id = self.add(robot);
+        work.entries.get_mut(entry).unwrap().assign(self.robots.get_mut(id).unwrap());
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector2;
+    use rand::Rng;
+    use items::{Item, ItemKind, ItemPhysics, ItemState, Items};
+    use rng::GameRng;
+    use tiles::Tiles;
+    use arena::Handle;
+    use super::{Robot, RobotState, Robots, WorkQueue, WorkEntry, WorkKind};
+    use super::{FOOD_ENERGY, LOW_ENERGY, MAX_ENERGY, MOVE_ENERGY, WEAR_PER_ENERGY, WORK_ENERGY};
+
+    #[test]
+    fn late_delivery_takes_back_its_promise() {
+        let mut tiles = Tiles::from_rows(&[
+            "...",
+            ".c.",
+        ]);
+        let site = Vector2::new(1, 0);
+        let mut items = Items::new(ItemPhysics::default());
+        let mut work = WorkQueue::new();
+        let mut rng = GameRng::from_seed(1);
+
+        // Two robots on site with the only food item the crate needs, both promised to bring it
+        let mut robots = Robots::new();
+        let mut carried = Vec::new();
+        for _ in 0..2 {
+            let item = items.add(Item::new(ItemKind::Food, Vector2::new(1.5, 0.5)));
+            items.get_mut(item).unwrap().state = ItemState::Carried;
+            items.get_mut(item).unwrap().claimed = true;
+            carried.push(item);
+
+            let mut robot = Robot::new(Vector2::new(1.5, 0.5));
+            robot.current_state = RobotState::Building(site);
+            robot.inventory = Some(item);
+            robot.promised = Some((site, ItemKind::Food));
+            tiles.get_mut(site.x, site.y).unwrap().promise_resource(ItemKind::Food);
+            robots.add(robot);
+        }
+
+        for (_, robot) in robots.robots.iter_mut() {
+            robot.update(0.1, &mut items, &mut tiles, &mut work, &mut rng);
+            assert_eq!(robot.inventory, None);
+            assert_eq!(robot.promised, None);
+        }
+
+        // One got used, the other is dropped for someone else and no more food is on its way
+        assert_eq!(tiles.get(site.x, site.y).unwrap().unpromised_resource(), Some(ItemKind::Mineral));
+        assert_eq!(tiles.promised(site.x, site.y, ItemKind::Food), 0);
+        assert!(items.get(carried[0]).is_none());
+        let leftover = items.get(carried[1]).unwrap();
+        assert_eq!(leftover.state, ItemState::Falling);
+        assert!(!leftover.claimed);
+    }
+
+    #[test]
+    fn breakdowns_are_checked_every_step() {
+        let mut robot = Robot::new(Vector2::new(0.5, 0.5));
+        let mut rng = GameRng::from_seed(1);
+
+        // Robots without any wear can't break down, so there's nothing to draw
+        assert!(!robot.breaks_down(0.1, &mut rng));
+        assert_eq!(rng.next_u32(), GameRng::from_seed(1).next_u32());
+
+        // Worn ones draw every time, no matter how short the step
+        robot.wear = 1.0;
+        let mut rng = GameRng::from_seed(1);
+        let mut expected = GameRng::from_seed(1);
+        for _ in 0..10 {
+            robot.breaks_down(0.01, &mut rng);
+            expected.gen_range(0.0f32, 1.0);
+        }
+        assert_eq!(rng.next_u32(), expected.next_u32());
+    }
+
+    #[test]
+    fn breaking_down_while_clearing_finishes_the_work() {
+        let mut tiles = Tiles::from_rows(&["..."]);
+        let mut items = Items::new(ItemPhysics::default());
+        let mut work = WorkQueue::new();
+
+        let residue = items.add(Item::new(ItemKind::Residue, Vector2::new(1.5, 0.5)));
+        items.get_mut(residue).unwrap().state = ItemState::Carried;
+        items.get_mut(residue).unwrap().claimed = true;
+        let entry = work.publish(WorkEntry::new(WorkKind::ClearDebris(residue), Vector2::new(1, 0)));
+
+        let mut robots = Robots::new();
+        let mut robot = Robot::new(Vector2::new(1.5, 0.5));
+        robot.assigned_work = Some(entry);
+        robot.current_state = RobotState::Clearing(residue);
+        robot.inventory = Some(residue);
+        let id = robots.add(robot);
+        robots.robots.remove(id).unwrap().break_down(&mut items, &mut tiles, &mut work);
+
+        // The residue gets new work once it's come to rest again
+        assert!(work.get(entry).is_none());
+        let item = items.get(residue).unwrap();
+        assert_eq!(item.state, ItemState::Falling);
+        assert!(!item.claimed);
+    }
+
+    #[test]
+    fn resources_are_counted_per_kind() {
+        let mut tiles = Tiles::from_rows(&["c"]);
+        {
+            let tile = tiles.get_mut(0, 0).unwrap();
+            tile.promise_resource(ItemKind::Food);
+            assert_eq!(tile.unpromised_resource(), Some(ItemKind::Mineral));
+
+            // Each kind only counts towards itself, and only as often as the recipe asks for it
+            assert!(tile.apply_resource(ItemKind::Food));
+            assert!(!tile.apply_resource(ItemKind::Food));
+            assert!(!tile.apply_resource(ItemKind::Residue));
+            assert!(tile.construction_needs_resources());
+        }
+        assert_eq!(tiles.promised(0, 0, ItemKind::Food), 0);
+
+        let tile = tiles.get_mut(0, 0).unwrap();
+        assert!(tile.apply_resource(ItemKind::Mineral));
+        assert!(!tile.construction_needs_resources());
+        assert_eq!(tile.unpromised_resource(), None);
+    }
+
+    #[test]
+    fn finished_deconstruction_refunds_the_recipe() {
+        let mut tiles = Tiles::from_rows(&["c.."]);
+        let site = Vector2::new(0, 0);
+        let class = tiles.get(0, 0).unwrap().under_construction_class().unwrap();
+        let def = tiles.defs().get(class).clone();
+        tiles.get_mut(0, 0).unwrap().set_class(class);
+        tiles.get_mut(0, 0).unwrap().set_deconstruction(&def);
+
+        let mut items = Items::new(ItemPhysics::default());
+        let mut work = WorkQueue::new();
+        let mut rng = GameRng::from_seed(1);
+        let entry = work.publish(WorkEntry::new(WorkKind::Deconstruct, site));
+
+        let mut robots = Robots::new();
+        let mut robot = Robot::new(Vector2::new(0.5, 0.5));
+        robot.assigned_work = Some(entry);
+        robot.current_state = RobotState::Deconstructing(site);
+        let id = robots.add(robot);
+        robots.robots.get_mut(id).unwrap().update(def.build_time, &mut items, &mut tiles, &mut work, &mut rng);
+
+        // The structure's gone and exactly what it cost is lying where it was
+        assert_eq!(tiles.get(0, 0).unwrap().class(), tiles.defs().empty());
+        assert!(!tiles.get(0, 0).unwrap().is_marked_for_deconstruction());
+        assert!(work.get(entry).is_none());
+        for kind in ItemKind::all() {
+            let dropped = items.iter().filter(|&(_, item)| item.kind == *kind).count() as u32;
+            assert_eq!(dropped, def.cost.get(*kind), "{:?}", kind);
+        }
+        assert!(robots.robots.get(id).unwrap().current_state.is_waiting());
+    }
+
+    #[test]
+    fn moving_and_building_use_energy() {
+        let mut tiles = Tiles::from_rows(&["c.."]);
+        let site = Vector2::new(0, 0);
+        tiles.get_mut(0, 0).unwrap().apply_resource(ItemKind::Food);
+        tiles.get_mut(0, 0).unwrap().apply_resource(ItemKind::Mineral);
+        let mut items = Items::new(ItemPhysics::default());
+        let mut work = WorkQueue::new();
+        let mut rng = GameRng::from_seed(1);
+        let entry = work.publish(WorkEntry::new(WorkKind::Build, site));
+
+        let mut robots = Robots::new();
+        let mut robot = Robot::new(Vector2::new(2.5, 0.5));
+        robot.assigned_work = Some(entry);
+        robot.current_state = RobotState::Building(site);
+        let id = robots.add(robot);
+        let robot = robots.robots.get_mut(id).unwrap();
+
+        // Walking the two tiles to the site
+        while robot.position != Vector2::new(0.5, 0.5) {
+            robot.update(0.1, &mut items, &mut tiles, &mut work, &mut rng);
+        }
+        assert!((robot.energy - (MAX_ENERGY - 2.0 * MOVE_ENERGY)).abs() < 0.01, "{}", robot.energy);
+
+        // And a bit of building once it's there
+        let energy = robot.energy;
+        robot.update(0.1, &mut items, &mut tiles, &mut work, &mut rng);
+        assert_eq!(robot.current_state, RobotState::Building(site));
+        assert!((robot.energy - (energy - WORK_ENERGY * 0.1)).abs() < 0.001, "{}", robot.energy);
+        assert!((robot.wear - (MAX_ENERGY - robot.energy) * WEAR_PER_ENERGY).abs() < 0.0001);
+    }
+
+    #[test]
+    fn low_energy_interrupts_building_until_recharged() {
+        let mut tiles = Tiles::from_rows(&["C..c"]);
+        let site = Vector2::new(3, 0);
+        tiles.get_mut(3, 0).unwrap().apply_resource(ItemKind::Food);
+        tiles.get_mut(3, 0).unwrap().apply_resource(ItemKind::Mineral);
+        let mut items = Items::new(ItemPhysics::default());
+        let mut work = WorkQueue::new();
+        let mut rng = GameRng::from_seed(1);
+        let entry = work.publish(WorkEntry::new(WorkKind::Build, site));
+
+        let mut robots = Robots::new();
+        let mut robot = Robot::new(Vector2::new(3.5, 0.5));
+        robot.assigned_work = Some(entry);
+        robot.current_state = RobotState::Building(site);
+        robot.energy = LOW_ENERGY - 1.0;
+        let id = robots.add(robot);
+        let robot = robots.robots.get_mut(id).unwrap();
+
+        robot.update(0.1, &mut items, &mut tiles, &mut work, &mut rng);
+        assert!(robot.is_recharging());
+        assert_eq!(robot.state_stack.first(), Some(&RobotState::Building(site)));
+
+        // Once it's full it goes back to the same work, nothing was built in the meantime
+        for _ in 0..100 {
+            if !robot.is_recharging() {
+                break;
+            }
+            robot.update(0.1, &mut items, &mut tiles, &mut work, &mut rng);
+        }
+        assert_eq!(robot.energy, MAX_ENERGY);
+        assert_eq!(robot.position.cast::<u32>(), Vector2::new(1, 0));
+        assert_eq!(robot.current_state, RobotState::Building(site));
+        assert!(robot.state_stack.is_empty());
+        assert_eq!(robot.assigned_work, Some(entry));
+        assert!(tiles.get(3, 0).unwrap().is_under_construction());
+    }
+
+    #[test]
+    fn food_is_eaten_when_no_charger_can_be_reached() {
+        let mut tiles = Tiles::from_rows(&[
+            "..#..",
+            "C.#..",
+        ]);
+        let mut items = Items::new(ItemPhysics::default());
+        let mut work = WorkQueue::new();
+        let mut rng = GameRng::from_seed(1);
+        let food = items.add(Item::new(ItemKind::Food, Vector2::new(4.5, 0.5)));
+        items.get_mut(food).unwrap().state = ItemState::Static;
+
+        let mut robots = Robots::new();
+        let mut robot = Robot::new(Vector2::new(3.5, 0.5));
+        robot.energy = LOW_ENERGY - 1.0;
+        let id = robots.add(robot);
+        let robot = robots.robots.get_mut(id).unwrap();
+
+        robot.update(0.1, &mut items, &mut tiles, &mut work, &mut rng);
+        assert!(robot.is_recharging());
+        assert!(items.get(food).unwrap().claimed);
+
+        for _ in 0..100 {
+            if items.get(food).is_none() {
+                break;
+            }
+            robot.update(0.1, &mut items, &mut tiles, &mut work, &mut rng);
+        }
+        assert!(items.get(food).is_none());
+        assert!(!robot.is_recharging());
+        assert!(robot.energy > LOW_ENERGY - 1.0 + FOOD_ENERGY - 2.0, "{}", robot.energy);
+    }
+
+    #[test]
+    fn each_kind_of_work_starts_its_own_state() {
+        let mut tiles = Tiles::from_rows(&["...."]);
+        let mut items = Items::new(ItemPhysics::default());
+        let mut work = WorkQueue::new();
+        let mut rng = GameRng::from_seed(1);
+
+        let food = items.add(Item::new(ItemKind::Food, Vector2::new(0.5, 0.5)));
+        let residue = items.add(Item::new(ItemKind::Residue, Vector2::new(0.5, 0.5)));
+        let wreck = items.add(Item::new(ItemKind::Wreck, Vector2::new(0.5, 0.5)));
+        let target = Vector2::new(2, 0);
+        let kinds = [
+            (WorkKind::Build, RobotState::Building(target)),
+            (WorkKind::Deconstruct, RobotState::Deconstructing(target)),
+            (WorkKind::Haul(food), RobotState::Hauling(food, target)),
+            (WorkKind::ClearDebris(residue), RobotState::Clearing(residue)),
+            (WorkKind::Repair(wreck), RobotState::Hauling(wreck, target)),
+        ];
+
+        let mut robots = Robots::new();
+        for &(kind, ref state) in &kinds {
+            let entry = work.publish(WorkEntry::new(kind, target));
+            let mut robot = Robot::new(Vector2::new(0.5, 0.5));
+            robot.assigned_work = Some(entry);
+            let id = robots.add(robot);
+
+            let robot = robots.robots.get_mut(id).unwrap();
+            robot.update(0.1, &mut items, &mut tiles, &mut work, &mut rng);
+            assert_eq!(robot.current_state, *state);
+        }
+    }
+
+    #[test]
+    fn only_structure_work_changes_structures() {
+        let mut work = WorkQueue::new();
+        let item = Items::new(ItemPhysics::default()).add(Item::new(ItemKind::Food, Vector2::new(0.5, 0.5)));
+        let haul = work.publish(WorkEntry::new(WorkKind::Haul(item), Vector2::new(0, 0)));
+        let build = work.publish(WorkEntry::new(WorkKind::Build, Vector2::new(1, 0)));
+
+        work.finish(haul);
+        assert!(!work.take_structures_changed());
+        work.finish(build);
+        assert!(work.take_structures_changed());
+        assert!(!work.take_structures_changed());
+    }
+
+    // Build work at each tile with the priorities, in order
+    fn publish_all(work: &mut WorkQueue, entries: &[(u32, u32)]) -> Vec<Handle> {
+        entries.iter()
+            .map(|&(x, priority)| {
+                work.publish(WorkEntry::new(WorkKind::Build, Vector2::new(x, 0)).with_priority(priority))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn closest_robot_gets_the_work() {
+        let tiles = Tiles::from_rows(&["........"]);
+        let mut work = WorkQueue::new();
+        let entries = publish_all(&mut work, &[(0, 1)]);
+
+        let mut robots = Robots::new();
+        let far = robots.add(Robot::new(Vector2::new(6.5, 0.5)));
+        let near = robots.add(Robot::new(Vector2::new(2.5, 0.5)));
+        robots.assign_work(&mut work, &tiles);
+
+        assert_eq!(robots.robots.get(near).unwrap().assigned_work, Some(entries[0]));
+        assert_eq!(robots.robots.get(far).unwrap().assigned_work, None);
+    }
+
+    #[test]
+    fn distances_are_kept_until_forgotten() {
+        let mut tiles = Tiles::from_rows(&["..#.."]);
+        let mut work = WorkQueue::new();
+        let entry = work.publish(WorkEntry::new(WorkKind::Build, Vector2::new(4, 0)));
+
+        let mut robots = Robots::new();
+        let robot = robots.add(Robot::new(Vector2::new(0.5, 0.5)));
+        robots.assign_work(&mut work, &tiles);
+        assert_eq!(robots.robots.get(robot).unwrap().assigned_work, None);
+
+        // Opening up the wall only makes a difference once the old distances are gone
+        let empty = tiles.defs().empty();
+        tiles.get_mut(2, 0).unwrap().set_class(empty);
+        robots.assign_work(&mut work, &tiles);
+        assert_eq!(robots.robots.get(robot).unwrap().assigned_work, None);
+
+        robots.forget_distances();
+        robots.assign_work(&mut work, &tiles);
+        assert_eq!(robots.robots.get(robot).unwrap().assigned_work, Some(entry));
+    }
+
+    #[test]
+    fn higher_priority_goes_before_distance_and_age() {
+        let tiles = Tiles::from_rows(&["........"]);
+        let mut work = WorkQueue::new();
+        // Close but low priority, far and high priority, then the same again but newer
+        let entries = publish_all(&mut work, &[(1, 0), (7, 2), (7, 2)]);
+
+        let mut robots = Robots::new();
+        let robot = robots.add(Robot::new(Vector2::new(0.5, 0.5)));
+        robots.assign_work(&mut work, &tiles);
+        assert_eq!(robots.robots.get(robot).unwrap().assigned_work, Some(entries[1]));
+
+        // The next robot gets the other high priority one, even though it's just as far
+        let other = robots.add(Robot::new(Vector2::new(0.5, 0.5)));
+        robots.assign_work(&mut work, &tiles);
+        assert_eq!(robots.robots.get(other).unwrap().assigned_work, Some(entries[2]));
+    }
+}
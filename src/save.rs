@@ -0,0 +1,219 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use cgmath::Vector2;
+
+// Followed by the format version
+const MAGIC: &'static [u8; 8] = b"SHIFTSAV";
+
+// Bump when anything that gets saved changes layout
+pub const SAVE_VERSION: u32 = 19;
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    NotASave,
+    UnsupportedVersion(u32),
+    Corrupt(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveError::Io(ref e) =>
+                write!(f, "Couldn't read save: {}", e),
+            SaveError::NotASave =>
+                write!(f, "File isn't a save"),
+            SaveError::UnsupportedVersion(version) =>
+                write!(f, "Save is version {}, but only version {} is supported", version, SAVE_VERSION),
+            SaveError::Corrupt(ref reason) =>
+                write!(f, "Save is corrupt: {}", reason),
+        }
+    }
+}
+
+impl Error for SaveError {
+    fn description(&self) -> &str {
+        match *self {
+            SaveError::Io(_) => "couldn't read save",
+            SaveError::NotASave => "file isn't a save",
+            SaveError::UnsupportedVersion(_) => "unsupported save version",
+            SaveError::Corrupt(_) => "save is corrupt",
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+// Numbers are little endian
+pub struct SaveWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> SaveWriter<W> {
+    pub fn new(out: W) -> io::Result<Self> {
+        let mut writer = SaveWriter {
+            out: out,
+        };
+
+        writer.out.write_all(MAGIC)?;
+        writer.write_u32(SAVE_VERSION)?;
+
+        Ok(writer)
+    }
+
+    pub fn write_u8(&mut self, value: u8) -> io::Result<()> {
+        self.out.write_all(&[value])
+    }
+
+    pub fn write_bool(&mut self, value: bool) -> io::Result<()> {
+        self.write_u8(if value { 1 } else { 0 })
+    }
+
+    pub fn write_u32(&mut self, value: u32) -> io::Result<()> {
+        self.out.write_all(&[
+            value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8
+        ])
+    }
+
+    pub fn write_u64(&mut self, value: u64) -> io::Result<()> {
+        self.write_u32(value as u32)?;
+        self.write_u32((value >> 32) as u32)
+    }
+
+    pub fn write_f32(&mut self, value: f32) -> io::Result<()> {
+        // Store the exact bits, so loading gives back exactly the same value
+        self.write_u32(value.to_bits())
+    }
+
+    pub fn write_vec2_u32(&mut self, value: Vector2<u32>) -> io::Result<()> {
+        self.write_u32(value.x)?;
+        self.write_u32(value.y)
+    }
+
+    pub fn write_vec2_f32(&mut self, value: Vector2<f32>) -> io::Result<()> {
+        self.write_f32(value.x)?;
+        self.write_f32(value.y)
+    }
+
+    pub fn write_len(&mut self, len: usize) -> io::Result<()> {
+        self.write_u32(len as u32)
+    }
+
+    pub fn write_string(&mut self, value: &str) -> io::Result<()> {
+        self.write_len(value.len())?;
+        self.out.write_all(value.as_bytes())
+    }
+
+    pub fn write_option<T, F>(&mut self, value: Option<T>, f: F) -> io::Result<()>
+        where F: FnOnce(&mut Self, T) -> io::Result<()>
+    {
+        if let Some(value) = value {
+            self.write_bool(true)?;
+            f(self, value)
+        } else {
+            self.write_bool(false)
+        }
+    }
+}
+
+pub struct SaveReader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> SaveReader<R> {
+    pub fn new(input: R) -> Result<Self, SaveError> {
+        let mut reader = SaveReader {
+            input: input,
+        };
+
+        let mut magic = [0; 8];
+        reader.input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SaveError::NotASave);
+        }
+
+        let version = reader.read_u32()?;
+        if version != SAVE_VERSION {
+            return Err(SaveError::UnsupportedVersion(version));
+        }
+
+        Ok(reader)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveError> {
+        let mut bytes = [0; 1];
+        self.input.read_exact(&mut bytes)?;
+        Ok(bytes[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SaveError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => Err(SaveError::Corrupt(format!("{} isn't a valid bool", v))),
+        }
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SaveError> {
+        let mut bytes = [0; 4];
+        self.input.read_exact(&mut bytes)?;
+        Ok(bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24)
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SaveError> {
+        let low = self.read_u32()? as u64;
+        let high = self.read_u32()? as u64;
+        Ok(low | high << 32)
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, SaveError> {
+        // Nothing we save is ever infinite or NaN, and they'd only cause trouble further on
+        let value = f32::from_bits(self.read_u32()?);
+        if !value.is_finite() {
+            return Err(SaveError::Corrupt(format!("{} isn't a valid number", value)));
+        }
+        Ok(value)
+    }
+
+    pub fn read_vec2_u32(&mut self) -> Result<Vector2<u32>, SaveError> {
+        let x = self.read_u32()?;
+        let y = self.read_u32()?;
+        Ok(Vector2::new(x, y))
+    }
+
+    pub fn read_vec2_f32(&mut self) -> Result<Vector2<f32>, SaveError> {
+        let x = self.read_f32()?;
+        let y = self.read_f32()?;
+        Ok(Vector2::new(x, y))
+    }
+
+    pub fn read_len(&mut self) -> Result<usize, SaveError> {
+        Ok(self.read_u32()? as usize)
+    }
+
+    pub fn read_string(&mut self) -> Result<String, SaveError> {
+        let len = self.read_len()?;
+        let mut bytes = Vec::new();
+        (&mut self.input).take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(SaveError::Corrupt("string runs past the end of the save".to_string()));
+        }
+
+        String::from_utf8(bytes).map_err(|_| SaveError::Corrupt("string isn't valid UTF-8".to_string()))
+    }
+
+    pub fn read_option<T, F>(&mut self, f: F) -> Result<Option<T>, SaveError>
+        where F: FnOnce(&mut Self) -> Result<T, SaveError>
+    {
+        if self.read_bool()? {
+            Ok(Some(f(self)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use items::{ItemKind, Recipe};
+use map::MapLoadError;
+use save::{SaveWriter, SaveReader, SaveError};
+use tmx::TilesetTile;
+
+// Most robots working on one construction at the same time
+const MAX_BUILDERS: u32 = 4;
+
+// What it takes for a structure to build a robot
+#[derive(Debug, Clone)]
+pub struct Fabrication {
+    // Once all items are there
+    pub time: f32,
+    pub cost: Recipe,
+}
+
+impl Fabrication {
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_f32(self.time)?;
+        self.cost.write_save(w)
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        Ok(Fabrication {
+            time: r.read_f32()?,
+            cost: Recipe::read_save(r)?,
+        })
+    }
+}
+
+// Read from the custom properties on the tileset's tiles
+#[derive(Debug, Clone)]
+pub struct TileDef {
+    pub name: String,
+    pub texture: String,
+    // Blocks robots and items
+    pub solid: bool,
+    // Built by robots, placed ones start out as construction sites
+    pub structure: bool,
+    // Can be ordered by the player
+    pub buildable: bool,
+    pub build_time: f32,
+    pub cost: Recipe,
+    // If this builds robots once it's finished
+    pub fabricator: Option<Fabrication>,
+    // If robots can recharge next to this once it's finished
+    pub charger: bool,
+}
+
+impl TileDef {
+    // One per item it needs
+    pub fn max_builders(&self) -> u32 {
+        cmp::max(1, cmp::min(self.cost.total(), MAX_BUILDERS))
+    }
+
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_string(&self.name)?;
+        w.write_string(&self.texture)?;
+        w.write_bool(self.solid)?;
+        w.write_bool(self.structure)?;
+        w.write_bool(self.buildable)?;
+        w.write_f32(self.build_time)?;
+        self.cost.write_save(w)?;
+        w.write_option(self.fabricator.as_ref(), |w, v| v.write_save(w))?;
+        w.write_bool(self.charger)
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        Ok(TileDef {
+            name: r.read_string()?,
+            texture: r.read_string()?,
+            solid: r.read_bool()?,
+            structure: r.read_bool()?,
+            buildable: r.read_bool()?,
+            build_time: r.read_f32()?,
+            cost: Recipe::read_save(r)?,
+            fabricator: r.read_option(Fabrication::read_save)?,
+            charger: r.read_bool()?,
+        })
+    }
+}
+
+fn property<T: FromStr>(tile: &TilesetTile, name: &str, default: T) -> Result<T, MapLoadError> {
+    match tile.properties.get(name) {
+        Some(value) => value.parse().map_err(|_| MapLoadError::InvalidTileProperty {
+            tile: tile.id,
+            property: name.to_string(),
+            value: value.clone(),
+        }),
+        None => Ok(default),
+    }
+}
+
+// A tile's class is its index in here
+pub struct TileDefs {
+    defs: Vec<TileDef>,
+    empty: u32,
+    fill: u32,
+}
+
+impl TileDefs {
+    pub fn load(tileset: &[TilesetTile]) -> Result<Self, MapLoadError> {
+        let mut defs: HashMap<u32, TileDef> = HashMap::new();
+        let mut empty = None;
+        let mut fill = None;
+
+        for tile in tileset {
+            let texture = tile.image.clone()
+                .ok_or_else(|| MapLoadError::InvalidTileset(format!("tile {} has no image", tile.id)))?;
+
+            // The cost is given per kind of item, as "cost_food" and so on
+            let mut cost = Recipe::new();
+            let mut fabricate_cost = Recipe::new();
+            for kind in ItemKind::all() {
+                cost.set(*kind, property(tile, &format!("cost_{}", kind.name()), 0)?);
+                fabricate_cost.set(*kind, property(tile, &format!("fabricate_cost_{}", kind.name()), 0)?);
+            }
+
+            let fabricator = if property(tile, "fabricator", false)? {
+                Some(Fabrication {
+                    time: property(tile, "fabricate_time", 10.0)?,
+                    cost: fabricate_cost,
+                })
+            } else {
+                None
+            };
+
+            let def = TileDef {
+                name: property(tile, "name", format!("tile {}", tile.id))?,
+                texture: texture,
+                solid: property(tile, "solid", false)?,
+                structure: property(tile, "structure", false)?,
+                buildable: property(tile, "buildable", false)?,
+                build_time: property(tile, "build_time", 2.0)?,
+                cost: cost,
+                fabricator: fabricator,
+                charger: property(tile, "charger", false)?,
+            };
+            if defs.insert(tile.id, def).is_some() {
+                return Err(MapLoadError::InvalidTileset(format!("tile {} is in the tileset more than once", tile.id)));
+            }
+
+            // What's left when a structure is removed, and what goes where the map has no tile
+            if property(tile, "empty", false)? {
+                if empty.is_some() {
+                    return Err(MapLoadError::InvalidTileset("more than one tile is marked empty".to_string()));
+                }
+                empty = Some(tile.id);
+            }
+            if property(tile, "fill", false)? {
+                if fill.is_some() {
+                    return Err(MapLoadError::InvalidTileset("more than one tile is marked fill".to_string()));
+                }
+                fill = Some(tile.id);
+            }
+        }
+
+        // Classes are used as indices, so there can't be gaps
+        let mut ordered = Vec::new();
+        for id in 0..defs.len() as u32 {
+            ordered.push(defs.remove(&id)
+                .ok_or_else(|| MapLoadError::InvalidTileset(format!("tile {} is missing", id)))?);
+        }
+
+        let empty = empty.ok_or_else(|| MapLoadError::InvalidTileset("no tile is marked empty".to_string()))?;
+        if ordered[empty as usize].solid {
+            return Err(MapLoadError::InvalidTileset("the empty tile can't be solid".to_string()));
+        }
+
+        Ok(TileDefs {
+            defs: ordered,
+            empty: empty,
+            fill: fill.unwrap_or(empty),
+        })
+    }
+
+    pub fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_len(self.defs.len())?;
+        for def in &self.defs {
+            def.write_save(w)?;
+        }
+        w.write_u32(self.empty)?;
+        w.write_u32(self.fill)
+    }
+
+    pub fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let mut defs = Vec::new();
+        for _ in 0..r.read_len()? {
+            defs.push(TileDef::read_save(r)?);
+        }
+        let empty = r.read_u32()?;
+        let fill = r.read_u32()?;
+
+        if empty as usize >= defs.len() || fill as usize >= defs.len() {
+            return Err(SaveError::Corrupt("tile definitions are inconsistent".to_string()));
+        }
+
+        Ok(TileDefs {
+            defs: defs,
+            empty: empty,
+            fill: fill,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.defs.len()
+    }
+
+    pub fn get(&self, class: u32) -> &TileDef {
+        &self.defs[class as usize]
+    }
+
+    pub fn iter<'a>(&'a self) -> ::std::slice::Iter<'a, TileDef> {
+        self.defs.iter()
+    }
+
+    // What's left when nothing is there
+    pub fn empty(&self) -> u32 {
+        self.empty
+    }
+
+    // Used where the map doesn't have a tile
+    pub fn fill(&self) -> u32 {
+        self.fill
+    }
+
+    pub fn buildable(&self) -> Vec<u32> {
+        (0..self.defs.len() as u32).filter(|c| self.get(*c).buildable).collect()
+    }
+}
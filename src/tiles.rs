@@ -1,26 +1,34 @@
+use std::io::{self, Read, Write};
 use cgmath::Vector2;
 use tiled::Map;
-use robots::{WorkQueue, WorkEntry};
+use robots::{WorkQueue, WorkEntry, WorkKind};
+use map::MapLoadError;
+use items::{ItemKind, Recipe};
+use save::{SaveWriter, SaveReader, SaveError};
+use tile_defs::{TileDef, TileDefs};
 
 #[derive(Debug)]
 struct ConstructionData {
     class: u32,
     time_remaining: f32,
-    resources_remaining: u32,
+    resources_remaining: Recipe,
+    resources_promised: Recipe, // on their way, but not delivered yet
 }
 
 #[derive(Debug)]
 pub struct Tile {
     class: u32,
     construction: Option<ConstructionData>,
+    deconstruction: Option<f32>, // time remaining
+    stockpile: bool,
 }
 
 impl Tile {
-    fn from_raw_id(raw_class: u32) -> Self {
+    fn from_raw_id(raw_class: u32, defs: &TileDefs) -> Self {
         // Get the actual ID
         let mut class = {
             if raw_class == 0 {
-                1 // If there's no tile here, use the default tile, solid flesh
+                defs.fill() // If there's no tile here, use the default tile
             } else {
                 raw_class - 1 // Actual tile
             }
@@ -29,18 +37,22 @@ impl Tile {
         // See if we need to flip this tile to under construction
         // We do this for the initial structues and let the robots build it
         let mut construction = None;
-        if class == 2 || class == 3 {
+        let def = defs.get(class);
+        if def.structure {
             construction = Some(ConstructionData {
                 class: class,
-                time_remaining: 2.0,
-                resources_remaining: 1,
+                time_remaining: def.build_time,
+                resources_remaining: def.cost.clone(),
+                resources_promised: Recipe::new(),
             });
-            class = 0; // Empty
+            class = defs.empty();
         }
 
         Tile {
             class: class,
             construction: construction,
+            deconstruction: None,
+            stockpile: false,
         }
     }
 
@@ -48,8 +60,13 @@ impl Tile {
         self.class
     }
 
+    pub fn is_solid(&self, defs: &TileDefs) -> bool {
+        defs.get(self.class).solid
+    }
+
     pub fn set_class(&mut self, class: u32) {
         self.construction = None;
+        self.deconstruction = None;
         self.class = class;
     }
 
@@ -61,26 +78,127 @@ impl Tile {
         }
     }
 
-    /*pub fn under_construction_class(&self) -> Option<u32> {
-        self.construction.map(|v| v.0)
-    }*/
+    // Marked by the player as a place to bring loose items to
+    pub fn is_stockpile(&self) -> bool {
+        self.stockpile
+    }
+
+    pub fn set_stockpile(&mut self, stockpile: bool) {
+        self.stockpile = stockpile;
+    }
+
+    pub fn is_marked_for_deconstruction(&self) -> bool {
+        self.deconstruction.is_some()
+    }
+
+    pub fn set_deconstruction(&mut self, def: &TileDef) {
+        self.deconstruction = Some(def.build_time);
+    }
+
+    // Turns into the empty class once it's taken apart
+    pub fn apply_deconstruct_time(&mut self, delta: f32, empty: u32) -> bool {
+        let done = {
+            let time_remaining = self.deconstruction.as_mut().unwrap();
+            *time_remaining -= delta;
+            *time_remaining <= 0.0
+        };
 
-    pub fn set_construction(&mut self, class: u32) {
+        if done {
+            self.set_class(empty);
+        }
+        done
+    }
+
+    pub fn under_construction_class(&self) -> Option<u32> {
+        self.construction.as_ref().map(|v| v.class)
+    }
+
+    // Returns the resources a construction was still waiting for
+    pub fn cancel_orders(&mut self) -> Option<Recipe> {
+        self.deconstruction = None;
+        self.construction.take().map(|v| v.resources_remaining)
+    }
+
+    pub fn set_construction(&mut self, class: u32, def: &TileDef) {
         self.construction = Some(ConstructionData {
             class: class,
-            time_remaining: 2.0,
-            resources_remaining: 1,
+            time_remaining: def.build_time,
+            resources_remaining: def.cost.clone(),
+            resources_promised: Recipe::new(),
         });
     }
 
     pub fn construction_needs_resources(&self) -> bool {
         let constr = self.construction.as_ref().unwrap();
-        constr.resources_remaining != 0
+        !constr.resources_remaining.is_empty()
     }
 
-    pub fn apply_resource(&mut self) {
+    // Next kind of item nobody's bringing yet
+    pub fn unpromised_resource(&self) -> Option<ItemKind> {
+        let constr = self.construction.as_ref().unwrap();
+        ItemKind::all().iter().cloned()
+            .find(|k| constr.resources_remaining.get(*k) > constr.resources_promised.get(*k))
+    }
+
+    // So other robots don't bring one too
+    pub fn promise_resource(&mut self, kind: ItemKind) {
+        let constr = self.construction.as_mut().unwrap();
+        let promised = constr.resources_promised.get(kind);
+        constr.resources_promised.set(kind, promised + 1);
+    }
+
+    // For when the item won't arrive after all
+    pub fn unpromise_resource(&mut self, kind: ItemKind) {
+        let constr = self.construction.as_mut().unwrap();
+        constr.resources_promised.take(kind);
+    }
+
+    // False if the construction didn't need the kind
+    pub fn apply_resource(&mut self, kind: ItemKind) -> bool {
         let constr = self.construction.as_mut().unwrap();
-        constr.resources_remaining -= 1;
+        if constr.resources_remaining.take(kind) {
+            constr.resources_promised.take(kind);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        w.write_u32(self.class)?;
+        w.write_option(self.construction.as_ref(), |w, constr| {
+            w.write_u32(constr.class)?;
+            w.write_f32(constr.time_remaining)?;
+            constr.resources_remaining.write_save(w)?;
+            constr.resources_promised.write_save(w)
+        })?;
+        w.write_option(self.deconstruction, |w, time| w.write_f32(time))?;
+        w.write_bool(self.stockpile)
+    }
+
+    fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let class = r.read_u32()?;
+        let construction = r.read_option(|r| {
+            let class = r.read_u32()?;
+            let time_remaining = r.read_f32()?;
+            let resources_remaining = Recipe::read_save(r)?;
+            let resources_promised = Recipe::read_save(r)?;
+            Ok(ConstructionData {
+                class: class,
+                time_remaining: time_remaining,
+                resources_remaining: resources_remaining,
+                resources_promised: resources_promised,
+            })
+        })?;
+        let deconstruction = r.read_option(|r| r.read_f32())?;
+        let stockpile = r.read_bool()?;
+
+        Ok(Tile {
+            class: class,
+            construction: construction,
+            deconstruction: deconstruction,
+            stockpile: stockpile,
+        })
     }
 
     pub fn apply_build_time(&mut self, delta: f32) -> bool {
@@ -107,16 +225,40 @@ pub struct Tiles {
     width: u32,
     height: u32,
     tiles: Vec<Tile>,
+    defs: TileDefs,
 }
 
 impl Tiles {
-    pub fn load(map: &Map, work: &mut WorkQueue) -> Self {
+    pub fn load(map: &Map, defs: TileDefs, work: &mut WorkQueue) -> Result<Self, MapLoadError> {
         // Process the tiles
-        let tiles_layer = map.layers.iter().find(|v| v.name == "Tiles").unwrap();
+        let tiles_layer = map.layers.iter()
+            .find(|v| v.name == "Tiles")
+            .ok_or_else(|| MapLoadError::MissingLayer("Tiles".to_string()))?;
+
+        // Make sure the layer actually covers the map, we index into it assuming it does
+        let height = tiles_layer.tiles.len() as u32;
+        let width = tiles_layer.tiles.first().map(|r| r.len() as u32).unwrap_or(0);
+        if height != map.height || tiles_layer.tiles.iter().any(|r| r.len() as u32 != map.width) {
+            return Err(MapLoadError::LayerSizeMismatch {
+                layer: tiles_layer.name.clone(),
+                expected: (map.width, map.height),
+                found: (width, height),
+            });
+        }
+
         let mut tiles = Vec::new();
-        for row in &tiles_layer.tiles {
-            for tile in row {
-                tiles.push(Tile::from_raw_id(*tile));
+        for (row, row_tiles) in tiles_layer.tiles.iter().enumerate() {
+            for (column, tile) in row_tiles.iter().enumerate() {
+                if *tile > defs.len() as u32 {
+                    return Err(MapLoadError::UnknownTile {
+                        layer: tiles_layer.name.clone(),
+                        gid: *tile,
+                        column: column as u32,
+                        row: row as u32,
+                    });
+                }
+
+                tiles.push(Tile::from_raw_id(*tile, &defs));
             }
         }
 
@@ -124,16 +266,53 @@ impl Tiles {
             width: map.width,
             height: map.height,
             tiles: tiles,
+            defs: defs,
         };
 
         // Spawn work items for each under construction tile
         tiles.for_each(|x, y, tile| {
-            if tile.is_under_construction() {
-                work.publish(WorkEntry::new(Vector2::new(x, y)));
+            if let Some(class) = tile.under_construction_class() {
+                let builders = tiles.defs().get(class).max_builders();
+                work.publish(WorkEntry::new(WorkKind::Build, Vector2::new(x, y)).with_max_workers(builders));
             }
         });
 
-        tiles
+        Ok(tiles)
+    }
+
+    pub fn write_save<W: Write>(&self, w: &mut SaveWriter<W>) -> io::Result<()> {
+        self.defs.write_save(w)?;
+        w.write_u32(self.width)?;
+        w.write_u32(self.height)?;
+        for tile in &self.tiles {
+            tile.write_save(w)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_save<R: Read>(r: &mut SaveReader<R>) -> Result<Self, SaveError> {
+        let defs = TileDefs::read_save(r)?;
+        let width = r.read_u32()?;
+        let height = r.read_u32()?;
+        let amount = width.checked_mul(height)
+            .ok_or_else(|| SaveError::Corrupt(format!("map size {}x{} is too large", width, height)))?;
+        let mut tiles = Vec::new();
+        for _ in 0..amount {
+            let tile = Tile::read_save(r)?;
+            let known = |class| (class as usize) < defs.len();
+            if !known(tile.class) || !tile.construction.as_ref().map(|c| known(c.class)).unwrap_or(true) {
+                return Err(SaveError::Corrupt(format!("tile class {} is unknown", tile.class)));
+            }
+            tiles.push(tile);
+        }
+
+        Ok(Tiles {
+            width: width,
+            height: height,
+            tiles: tiles,
+            defs: defs,
+        })
     }
 
     pub fn width(&self) -> u32 {
@@ -144,6 +323,10 @@ impl Tiles {
         self.height
     }
 
+    pub fn defs(&self) -> &TileDefs {
+        &self.defs
+    }
+
     fn position_for(&self, x: u32, y: u32) -> usize {
         let actual_y = self.height as usize - y as usize - 1; // Hacky flip, perhaps do this on loading
         x as usize + actual_y * self.width as usize
@@ -172,6 +355,24 @@ impl Tiles {
         }
     }
 
+    // Makes sure a tile position read from a save is on the map, a corrupt save could point anywhere
+    pub fn check_saved_pos(&self, pos: Vector2<u32>) -> Result<(), SaveError> {
+        if pos.x < self.width && pos.y < self.height {
+            Ok(())
+        } else {
+            Err(SaveError::Corrupt(format!("tile {}, {} is outside of the map", pos.x, pos.y)))
+        }
+    }
+
+    pub fn is_passable(&self, x: u32, y: u32) -> bool {
+        self.get(x, y).map(|v| !v.is_solid(&self.defs)).unwrap_or(false)
+    }
+
+    // A structure built over a stockpile blocks it
+    pub fn is_stockpile(&self, x: u32, y: u32) -> bool {
+        self.get(x, y).map(|v| v.is_stockpile() && !v.is_solid(&self.defs)).unwrap_or(false)
+    }
+
     pub fn for_each<F: FnMut(u32, u32, &Tile)>(&self, mut f: F) {
         for x in 0..self.width() {
             for y in 0..self.height() {
@@ -181,3 +382,51 @@ impl Tiles {
         }
     }
 }
+
+#[cfg(test)]
+impl Tiles {
+    // Builds a small map for tests out of rows of text, top row first like in the editor,
+    // . is empty, # is a wall and c is a crate construction needing a food and a mineral
+    pub fn from_rows(rows: &[&str]) -> Self {
+        use tmx::TilesetTile;
+
+        let tile = |id, properties: &[(&str, &str)]| TilesetTile {
+            id: id,
+            image: Some(String::new()),
+            properties: properties.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        let defs = TileDefs::load(&[
+            tile(0, &[("name", "empty"), ("empty", "true")]),
+            tile(1, &[("name", "wall"), ("solid", "true")]),
+            tile(2, &[("name", "crate"), ("solid", "true"), ("structure", "true"),
+                ("cost_food", "1"), ("cost_mineral", "1")]),
+            tile(3, &[("name", "charger"), ("solid", "true"), ("charger", "true")]),
+        ]).unwrap();
+
+        let mut tiles = Vec::new();
+        for row in rows {
+            for c in row.chars() {
+                let raw_class = match c {
+                    '.' => 1,
+                    '#' => 2,
+                    'c' => 3,
+                    'C' => 4,
+                    _ => panic!("Unknown tile '{}'", c),
+                };
+                tiles.push(Tile::from_raw_id(raw_class, &defs));
+            }
+        }
+
+        Tiles {
+            width: rows[0].len() as u32,
+            height: rows.len() as u32,
+            tiles: tiles,
+            defs: defs,
+        }
+    }
+
+    // How many items of the kind robots have promised to bring to the construction
+    pub fn promised(&self, x: u32, y: u32, kind: ItemKind) -> u32 {
+        self.get(x, y).unwrap().construction.as_ref().unwrap().resources_promised.get(kind)
+    }
+}
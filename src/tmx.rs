@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Cursor};
+use std::path::Path;
+use tiled::{self, Map};
+use xml::reader::{EventReader, XmlEvent};
+use map::MapLoadError;
+
+// The TMX files are small enough to keep all of it around
+struct Element {
+    name: String,
+    attributes: HashMap<String, String>,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(|v| &v[..])
+    }
+
+    fn children(&self, name: &str) -> Vec<&Element> {
+        self.children.iter().filter(|c| c.name == name).collect()
+    }
+
+    // Tiled keeps them in a "properties" child element
+    fn properties(&self) -> HashMap<String, String> {
+        let mut properties = HashMap::new();
+        for group in self.children("properties") {
+            for property in group.children("property") {
+                if let (Some(name), Some(value)) = (property.attribute("name"), property.attribute("value")) {
+                    properties.insert(name.to_string(), value.to_string());
+                }
+            }
+        }
+        properties
+    }
+}
+
+fn parse_elements<R: Read>(input: R) -> Result<Element, MapLoadError> {
+    let mut parser = EventReader::new(input);
+
+    // Elements that haven't been closed yet, the root is at the bottom
+    let mut open: Vec<Element> = Vec::new();
+    loop {
+        match parser.next() {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => {
+                open.push(Element {
+                    name: name.local_name,
+                    attributes: attributes.into_iter().map(|a| (a.name.local_name, a.value)).collect(),
+                    children: Vec::new(),
+                });
+            },
+            Ok(XmlEvent::EndElement { .. }) => {
+                let element = open.pop().unwrap();
+                if let Some(parent) = open.last_mut() {
+                    parent.children.push(element);
+                } else {
+                    return Ok(element);
+                }
+            },
+            Ok(XmlEvent::EndDocument) => return Err(MapLoadError::Xml("unexpected end of file".to_string())),
+            Ok(_) => (),
+            Err(e) => return Err(MapLoadError::Xml(format!("{}", e))),
+        }
+    }
+}
+
+// As it's defined in the editor
+pub struct TilesetTile {
+    pub id: u32,
+    // Relative to the working directory
+    pub image: Option<String>,
+    pub properties: HashMap<String, String>,
+}
+
+// In pixels, with the origin at the top left like in the editor
+pub struct TmxObject {
+    pub object_type: Option<String>,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    // Rather than an ellipse, polygon or point
+    pub rectangle: bool,
+    pub properties: HashMap<String, String>,
+}
+
+pub struct TmxObjectGroup {
+    pub name: String,
+    pub objects: Vec<TmxObject>,
+}
+
+fn parse_object(object: &Element, group: &str, index: usize) -> Result<TmxObject, MapLoadError> {
+    // Attributes that aren't there are 0, but ones that are there have to be numbers
+    let number = |name: &str| match object.attribute(name) {
+        Some(value) => value.parse().map_err(|_| MapLoadError::InvalidObjectProperty {
+            group: group.to_string(),
+            index: index,
+            property: name.to_string(),
+            value: value.to_string(),
+        }),
+        None => Ok(0.0),
+    };
+    let shaped = ["ellipse", "polygon", "polyline", "point"].iter()
+        .any(|shape| !object.children(shape).is_empty());
+
+    Ok(TmxObject {
+        // Newer versions of the editor call the type a class
+        object_type: object.attribute("type").or_else(|| object.attribute("class"))
+            .and_then(|v| if v.is_empty() { None } else { Some(v.to_string()) }),
+        x: number("x")?,
+        y: number("y")?,
+        width: number("width")?,
+        height: number("height")?,
+        rectangle: !shaped,
+        properties: object.properties(),
+    })
+}
+
+// With what the tiled crate doesn't read itself
+pub struct MapFile {
+    pub map: Map,
+    pub properties: HashMap<String, String>,
+    pub tileset_tiles: Vec<TilesetTile>,
+    // The tiled crate skips the types and properties
+    pub object_groups: Vec<TmxObjectGroup>,
+}
+
+impl MapFile {
+    pub fn load(path: &Path) -> Result<Self, MapLoadError> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+
+        // Images in the map are relative to the map itself
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        let map = tiled::parse(Cursor::new(&data[..]))?;
+        let root = parse_elements(Cursor::new(&data[..]))?;
+
+        // Read in all tiles of the first tileset, the tiles layer only uses that one
+        let mut tileset_tiles = Vec::new();
+        if let Some(tileset) = root.children("tileset").first() {
+            for tile in tileset.children("tile") {
+                let id = tile.attribute("id")
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| MapLoadError::Xml("tileset tile without a valid id".to_string()))?;
+                let image = tile.children("image").first()
+                    .and_then(|i| i.attribute("source"))
+                    .map(|s| directory.join(s).to_string_lossy().into_owned());
+                if let Some(ref image) = image {
+                    if !Path::new(image).is_file() {
+                        return Err(MapLoadError::InvalidTileset(format!("tile {}'s image {} doesn't exist", id, image)));
+                    }
+                }
+
+                tileset_tiles.push(TilesetTile {
+                    id: id,
+                    image: image,
+                    properties: tile.properties(),
+                });
+            }
+        }
+
+        let mut object_groups = Vec::new();
+        for group in root.children("objectgroup") {
+            let name = group.attribute("name").unwrap_or("").to_string();
+            let objects = group.children("object").into_iter().enumerate()
+                .map(|(i, object)| parse_object(object, &name, i))
+                .collect::<Result<_, _>>()?;
+
+            object_groups.push(TmxObjectGroup {
+                name: name,
+                objects: objects,
+            });
+        }
+
+        Ok(MapFile {
+            map: map,
+            properties: root.properties(),
+            tileset_tiles: tileset_tiles,
+            object_groups: object_groups,
+        })
+    }
+}
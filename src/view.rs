@@ -1,29 +1,39 @@
 use tungsten_glium2d::{Frontend2D, FrameRenderInfo, RenderTarget, View2D, TextureId, Rectangle};
-use model::GameModel;
+use shift_inc::items::{Freshness, ItemKind};
+use shift_inc::model::GameModel;
 
 pub struct View {
     tiles: Vec<TextureId>,
     food: TextureId,
+    mineral: TextureId,
+    residue: TextureId,
+    wreck: TextureId,
+    stale: TextureId,
+    rotten: TextureId,
     robot: TextureId,
     construction: TextureId,
+    stockpile: TextureId,
     selection: TextureId,
 }
 
 impl View {
-    pub fn new(frontend: &mut Frontend2D<GameModel>) -> Self {
-        // Load in textures
-        let tiles = vec!(
-            frontend.load_texture("./assets/background.png"),
-            frontend.load_texture("./assets/foreground.png"),
-            frontend.load_texture("./assets/wall.png"),
-            frontend.load_texture("./assets/core.png"),
-        );
+    pub fn new(frontend: &mut Frontend2D<GameModel>, model: &GameModel) -> Self {
+        // Load in textures, the tile textures in the same order as their classes
+        let tiles = model.map().tiles().defs().iter()
+            .map(|def| frontend.load_texture(&def.texture))
+            .collect();
 
         View {
             tiles: tiles,
             food: frontend.load_texture("./assets/food.png"),
+            mineral: frontend.load_texture("./assets/mineral.png"),
+            residue: frontend.load_texture("./assets/residue.png"),
+            wreck: frontend.load_texture("./assets/wreck.png"),
+            stale: frontend.load_texture("./assets/stale.png"),
+            rotten: frontend.load_texture("./assets/rotten.png"),
             robot: frontend.load_texture("./assets/robot.png"),
             construction: frontend.load_texture("./assets/construction.png"),
+            stockpile: frontend.load_texture("./assets/stockpile.png"),
             selection: frontend.load_texture("./assets/selection.png"),
         }
     }
@@ -41,8 +51,18 @@ impl View {
             };
             batch.rectangle(rect);
 
-            // If this tile's under construction, add a graphic for that
-            if tile.is_under_construction() {
+            // Mark out stockpiles
+            if tile.is_stockpile() {
+                let rect = Rectangle {
+                    texture: self.stockpile,
+                    position: [128.0 * x as f32 + 64.0, 128.0 * y as f32 + 64.0],
+                    size: [128.0, 128.0],
+                };
+                batch.rectangle(rect);
+            }
+
+            // If this tile's under construction or being taken apart, add a graphic for that
+            if tile.is_under_construction() || tile.is_marked_for_deconstruction() {
                 let rect = Rectangle {
                     texture: self.construction,
                     position: [128.0 * x as f32 + 64.0, 128.0 * y as f32 + 64.0],
@@ -65,11 +85,31 @@ impl View {
         // Render the items
         model.map().items().for_each(|item| {
             let rect = Rectangle {
-                texture: self.food,
+                texture: match item.kind {
+                    ItemKind::Food => self.food,
+                    ItemKind::Mineral => self.mineral,
+                    ItemKind::Residue => self.residue,
+                    ItemKind::Wreck => self.wreck,
+                },
                 position: [item.position[0] * 128.0, item.position[1] * 128.0],
                 size: [32.0, 32.0],
             };
             batch.rectangle(rect);
+
+            // Show how far the item has rotted
+            let overlay = match item.freshness() {
+                Freshness::Fresh => None,
+                Freshness::Stale => Some(self.stale),
+                Freshness::Rotten => Some(self.rotten),
+            };
+            if let Some(texture) = overlay {
+                let rect = Rectangle {
+                    texture: texture,
+                    position: [item.position[0] * 128.0, item.position[1] * 128.0],
+                    size: [32.0, 32.0],
+                };
+                batch.rectangle(rect);
+            }
         });
 
         // Render the selection indicator
@@ -82,9 +122,19 @@ impl View {
         batch.rectangle(rect);
     }
 
-    fn render_ui(&self, _model: &GameModel, info: &mut FrameRenderInfo) {
+    fn render_ui(&self, model: &GameModel, info: &mut FrameRenderInfo) {
         let camera = info.game_camera([0.0, 0.0]);
-        let _batch = camera.batch();
+        let batch = camera.batch();
+
+        // Show the structure that will be placed in the top left corner
+        if let Some(class) = model.selected_structure() {
+            let rect = Rectangle {
+                texture: self.tiles[class as usize],
+                position: [-1280.0/2.0 + 64.0, 720.0/2.0 - 64.0],
+                size: [96.0, 96.0],
+            };
+            batch.rectangle(rect);
+        }
     }
 }
 
@@ -0,0 +1,29 @@
+extern crate shift_inc;
+
+use std::path::Path;
+use shift_inc::model::GameModel;
+
+fn run(seed: u64, deltas: &[f32]) -> Vec<u8> {
+    let mut model = GameModel::load(Path::new("assets/map.tmx"), seed).unwrap();
+    for _ in 0..200 {
+        for delta in deltas {
+            model.update(*delta);
+        }
+    }
+
+    let mut bytes = Vec::new();
+    model.save(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn same_seed_gives_identical_results() {
+    let deltas = [0.016, 0.017, 0.021, 0.009];
+    assert_eq!(run(42, &deltas), run(42, &deltas));
+}
+
+#[test]
+fn different_seeds_give_different_results() {
+    let deltas = [0.016, 0.017, 0.021, 0.009];
+    assert!(run(1, &deltas) != run(2, &deltas));
+}
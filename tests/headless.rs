@@ -0,0 +1,26 @@
+extern crate shift_inc;
+
+use std::path::Path;
+use shift_inc::model::GameModel;
+
+fn dump(model: &GameModel) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    model.map().dump(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn long_ticks_run_every_step() {
+    let map = Path::new("tests/maps/valid.tmx");
+    let mut model = GameModel::load(map, 3).unwrap();
+    model.simulate(4, 1.0);
+
+    // A second's worth of steps for every tick, none of them dropped like a slow frame's would be
+    assert!(model.steps() >= 4 * 59);
+
+    let mut stepped = GameModel::load(map, 3).unwrap();
+    while stepped.steps() < model.steps() {
+        stepped.step();
+    }
+    assert_eq!(dump(&stepped), dump(&model));
+}
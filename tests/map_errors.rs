@@ -0,0 +1,174 @@
+extern crate shift_inc;
+
+use std::path::Path;
+use shift_inc::map::MapLoadError;
+use shift_inc::model::GameModel;
+
+fn load_error(name: &str) -> MapLoadError {
+    let path = format!("tests/maps/{}.tmx", name);
+    match GameModel::load(Path::new(&path), 1) {
+        Ok(_) => panic!("{} loaded without an error", path),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn valid_map_loads() {
+    // The other maps are this one with a single thing broken
+    assert!(GameModel::load(Path::new("tests/maps/valid.tmx"), 1).is_ok());
+}
+
+#[test]
+fn missing_layer_is_reported() {
+    match load_error("missing_layer") {
+        MapLoadError::MissingLayer(ref name) => assert_eq!(name, "Tiles"),
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn unexpected_layer_is_reported() {
+    match load_error("unexpected_layer") {
+        MapLoadError::UnexpectedLayer(ref name) => assert_eq!(name, "Decoration"),
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn unknown_tile_is_reported() {
+    match load_error("unknown_tile") {
+        MapLoadError::UnknownTile { ref layer, gid, column, row } => {
+            assert_eq!(layer, "Tiles");
+            assert_eq!((gid, column, row), (7, 2, 1));
+        },
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn layer_size_mismatch_is_reported() {
+    match load_error("size_mismatch") {
+        MapLoadError::LayerSizeMismatch { ref layer, expected, found } => {
+            assert_eq!(layer, "Tiles");
+            assert_eq!(expected, (4, 2));
+            assert_eq!(found, (3, 2));
+        },
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn missing_empty_tile_is_reported() {
+    match load_error("no_empty_tile") {
+        MapLoadError::InvalidTileset(ref reason) => assert_eq!(reason, "no tile is marked empty"),
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn invalid_cost_is_reported() {
+    match load_error("invalid_cost") {
+        MapLoadError::InvalidTileProperty { tile, ref property, ref value } => {
+            assert_eq!(tile, 1);
+            assert_eq!((&property[..], &value[..]), ("cost_food", "lots"));
+        },
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn missing_texture_is_reported() {
+    match load_error("missing_texture") {
+        MapLoadError::InvalidTileset(ref reason) => assert!(reason.contains("missing.png"), "{}", reason),
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn duplicate_tile_is_reported() {
+    match load_error("duplicate_tile") {
+        MapLoadError::InvalidTileset(ref reason) => {
+            assert_eq!(reason, "tile 0 is in the tileset more than once");
+        },
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn items_have_to_fall() {
+    for &(map, property, value) in &[
+        ("negative_gravity", "item_gravity", "-9.8"),
+        ("infinite_terminal_velocity", "item_terminal_velocity", "inf"),
+    ] {
+        match load_error(map) {
+            MapLoadError::InvalidMapProperty { property: ref p, value: ref v } => {
+                assert_eq!((&p[..], &v[..]), (property, value));
+            },
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+}
+
+#[test]
+fn infinite_spawn_rate_is_rejected() {
+    match load_error("infinite_rate") {
+        MapLoadError::InvalidObjectProperty { ref group, index, ref property, .. } => {
+            assert_eq!((&group[..], index), ("Food Spawners", 0));
+            assert_eq!(property, "rate");
+        },
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn flat_spawner_is_rejected() {
+    match load_error("flat_spawner") {
+        MapLoadError::InvalidObjectProperty { ref group, index, ref property, ref value } => {
+            assert_eq!((&group[..], index), ("Food Spawners", 0));
+            assert_eq!((&property[..], &value[..]), ("height", "0"));
+        },
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn only_the_camera_start_can_be_a_point() {
+    match load_error("flat_trigger") {
+        MapLoadError::InvalidObjectProperty { ref group, index, ref property, ref value } => {
+            assert_eq!((&group[..], index), ("Markers", 1));
+            assert_eq!((&property[..], &value[..]), ("width", "0"));
+        },
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn spawning_residue_is_rejected() {
+    match load_error("residue_spawner") {
+        MapLoadError::InvalidObjectProperty { ref property, ref value, .. } => {
+            assert_eq!((&property[..], &value[..]), ("kind", "residue"));
+        },
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn non_numeric_size_is_rejected() {
+    match load_error("invalid_size") {
+        MapLoadError::InvalidObjectProperty { ref group, index, ref property, ref value } => {
+            assert_eq!((&group[..], index), ("Robots", 0));
+            assert_eq!((&property[..], &value[..]), ("width", "wide"));
+        },
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn robots_inside_walls_are_rejected() {
+    match load_error("blocked_robots") {
+        MapLoadError::BlockedObject { ref group, index } => {
+            assert_eq!((&group[..], index), ("Robots", 0));
+        },
+        e => panic!("unexpected error: {}", e),
+    }
+}
@@ -0,0 +1,43 @@
+extern crate cgmath;
+extern crate shift_inc;
+
+use std::path::Path;
+use cgmath::Vector2;
+use shift_inc::model::{GameModel, GameButton};
+use shift_inc::replay::Recording;
+
+fn dump(model: &GameModel) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    model.map().dump(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn replay_reproduces_recorded_session() {
+    let map = "assets/map.tmx";
+    let mut model = GameModel::load(Path::new(map), 7).unwrap();
+    model.start_recording(map);
+
+    // Play a bit, placing a few constructions with uneven frame times
+    for i in 0..600 {
+        if i % 100 == 10 {
+            model.handle_mouse_move(Vector2::new(640 + i * 2, 360));
+            model.handle_keychange(GameButton::Interact, true);
+        }
+        if i % 100 == 12 {
+            model.handle_keychange(GameButton::Interact, false);
+        }
+        model.update(if i % 3 == 0 { 0.02 } else { 0.015 });
+    }
+
+    // Write the recording out and read it back in like the headless runner does
+    let mut bytes = Vec::new();
+    model.finish_recording().unwrap().write(&mut bytes).unwrap();
+    let recording = Recording::read(&bytes[..]).unwrap();
+
+    let mut replayed = GameModel::load(Path::new(recording.map()), recording.seed()).unwrap();
+    replayed.replay(&recording);
+
+    assert_eq!(replayed.steps(), model.steps());
+    assert_eq!(dump(&replayed), dump(&model));
+}
@@ -0,0 +1,96 @@
+extern crate shift_inc;
+
+use std::path::Path;
+use shift_inc::model::GameModel;
+use shift_inc::save::{SaveWriter, SaveReader, SaveError};
+use shift_inc::tiles::Tiles;
+
+fn save_bytes(model: &GameModel) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    model.save(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn save_load_save_is_identical() {
+    let mut model = GameModel::load(Path::new("assets/map.tmx"), 1234).unwrap();
+
+    // Run for a while so there's construction progress, items and busy robots
+    for _ in 0..1200 {
+        model.update(1.0 / 60.0);
+    }
+
+    let first = save_bytes(&model);
+    let loaded = GameModel::restore(&first[..]).unwrap();
+    let second = save_bytes(&loaded);
+
+    assert_eq!(first, second);
+}
+
+#[test]
+fn loaded_model_continues_identically() {
+    let mut model = GameModel::load(Path::new("assets/map.tmx"), 1234).unwrap();
+    for _ in 0..600 {
+        model.update(1.0 / 60.0);
+    }
+
+    let mut loaded = GameModel::restore(&save_bytes(&model)[..]).unwrap();
+
+    // Both should stay in lockstep, random numbers included
+    for _ in 0..600 {
+        model.update(1.0 / 60.0);
+        loaded.update(1.0 / 60.0);
+    }
+
+    assert_eq!(save_bytes(&model), save_bytes(&loaded));
+}
+
+#[test]
+fn invalid_number_is_corrupt() {
+    let model = GameModel::load(Path::new("assets/map.tmx"), 1234).unwrap();
+    let mut bytes = save_bytes(&model);
+
+    // The camera position comes right after the magic and version
+    bytes[12..16].copy_from_slice(&[0x00, 0x00, 0xc0, 0x7f]); // NaN
+    match GameModel::restore(&bytes[..]) {
+        Err(SaveError::Corrupt(_)) => (),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("save with a NaN camera position loaded"),
+    }
+}
+
+#[test]
+fn zeroed_rng_state_is_corrupt() {
+    let model = GameModel::load(Path::new("assets/map.tmx"), 1234).unwrap();
+    let mut bytes = save_bytes(&model);
+
+    // The generator's state comes after the camera position and the seed
+    for byte in &mut bytes[28..44] {
+        *byte = 0;
+    }
+    match GameModel::restore(&bytes[..]) {
+        Err(SaveError::Corrupt(_)) => (),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("save with a stuck random number generator loaded"),
+    }
+}
+
+#[test]
+fn oversized_map_is_corrupt() {
+    let model = GameModel::load(Path::new("assets/map.tmx"), 1234).unwrap();
+
+    let mut bytes = Vec::new();
+    {
+        let mut w = SaveWriter::new(&mut bytes).unwrap();
+        model.map().tiles().defs().write_save(&mut w).unwrap();
+        w.write_u32(0x10000).unwrap();
+        w.write_u32(0x10000).unwrap();
+    }
+
+    let mut r = SaveReader::new(&bytes[..]).unwrap();
+    match Tiles::read_save(&mut r) {
+        Err(SaveError::Corrupt(_)) => (),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("map too large to index loaded"),
+    }
+}